@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+/*
+ * registry of the biomes found in the world, and the climate of each vanilla biome, which decides
+ * the colour that grass, leaves and water are tinted in it
+ */
+use image::RgbaImage;
+
+// the biome id of plains, which is always registered first and used wherever a chunk has no biomes
+pub const PLAINS: u16 = 0;
+
+// the colour of water in most biomes
+const DEFAULT_WATER: u32 = 0x3f76e4;
+
+// the colours at the corners of the grass and foliage colormaps, for when there's no resource pack
+// to read them from: hot and wet, hot and dry, then cold
+const GRASS_CORNERS: [u32; 3] = [0x47cd33, 0xbfb755, 0x80b497];
+const FOLIAGE_CORNERS: [u32; 3] = [0x1abf00, 0xaea42a, 0x60a17b];
+
+/**
+ * the colour a block is tinted, which for most tinted blocks depends on the biome it's in
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tint {
+  None,
+  Grass,
+  Foliage,
+  Water,
+  // the same colour everywhere, like birch leaves
+  Fixed(u32)
+}
+
+/**
+ * how the grass colour of a biome differs from the one its climate gives it
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GrassModifier {
+  None,
+  // blended with a dark green
+  DarkForest,
+  // a set grass and foliage colour, like in badlands and swamps
+  Fixed(u32, u32)
+}
+
+/**
+ * the parts of a biome that decide how things in it are coloured
+ */
+#[derive(Clone, Copy, Debug)]
+struct Climate {
+  temperature: f32,
+  downfall: f32,
+  water: u32,
+  grass: GrassModifier
+}
+
+impl Climate {
+
+  const fn new (temperature: f32, downfall: f32) -> Climate {
+    Climate { temperature, downfall, water: DEFAULT_WATER, grass: GrassModifier::None }
+  }
+
+  const fn water (self, water: u32) -> Climate {
+    Climate { water, ..self }
+  }
+
+  const fn grass (self, grass: GrassModifier) -> Climate {
+    Climate { grass, ..self }
+  }
+
+}
+
+/**
+ * get the climate of a vanilla biome. biomes from mods or datapacks are given the climate of plains
+ */
+fn climate (name: &str) -> Climate {
+
+  let swamp = GrassModifier::Fixed(0x6a7039, 0x6a7039);
+  let badlands = GrassModifier::Fixed(0x90814d, 0x9e814d);
+
+  match name.strip_prefix("minecraft:").unwrap_or(name) {
+    "snowy_plains" | "ice_spikes" => Climate::new(0.0, 0.5),
+    "desert" | "savanna" | "savanna_plateau" | "windswept_savanna" => Climate::new(2.0, 0.0),
+    "badlands" | "eroded_badlands" | "wooded_badlands" => Climate::new(2.0, 0.0).grass(badlands),
+    "swamp" => Climate::new(0.8, 0.9).water(0x617b64).grass(swamp),
+    "mangrove_swamp" => Climate::new(0.8, 0.9).water(0x3a7a6a).grass(GrassModifier::Fixed(0x6a7039, 0x8db127)),
+    "forest" | "flower_forest" => Climate::new(0.7, 0.8),
+    "dark_forest" => Climate::new(0.7, 0.8).grass(GrassModifier::DarkForest),
+    "pale_garden" => Climate::new(0.7, 0.8).water(0x76889d).grass(GrassModifier::Fixed(0x778272, 0x878d76)),
+    "birch_forest" | "old_growth_birch_forest" => Climate::new(0.6, 0.6),
+    "old_growth_pine_taiga" => Climate::new(0.3, 0.8),
+    "taiga" | "old_growth_spruce_taiga" => Climate::new(0.25, 0.8),
+    "snowy_taiga" => Climate::new(-0.5, 0.4).water(0x3d57d6),
+    "windswept_hills" | "windswept_gravelly_hills" | "windswept_forest" | "stony_shore" => Climate::new(0.2, 0.3),
+    "jungle" | "bamboo_jungle" => Climate::new(0.95, 0.9),
+    "sparse_jungle" => Climate::new(0.95, 0.8),
+    "meadow" => Climate::new(0.5, 0.8).water(0x0e4ecf),
+    "cherry_grove" => Climate::new(0.5, 0.8).water(0x5db7ef).grass(GrassModifier::Fixed(0xb6db61, 0xb6db61)),
+    "grove" => Climate::new(-0.2, 0.8),
+    "snowy_slopes" => Climate::new(-0.3, 0.9),
+    "frozen_peaks" | "jagged_peaks" => Climate::new(-0.7, 0.9),
+    "stony_peaks" => Climate::new(1.0, 0.3),
+    "frozen_river" => Climate::new(0.0, 0.5).water(0x3938c9),
+    "snowy_beach" => Climate::new(0.05, 0.3).water(0x3d57d6),
+    "warm_ocean" => Climate::new(0.5, 0.5).water(0x43d5ee),
+    "lukewarm_ocean" | "deep_lukewarm_ocean" => Climate::new(0.5, 0.5).water(0x45adf2),
+    "cold_ocean" | "deep_cold_ocean" => Climate::new(0.5, 0.5).water(0x3d57d6),
+    "frozen_ocean" => Climate::new(0.0, 0.5).water(0x3938c9),
+    "deep_frozen_ocean" => Climate::new(0.5, 0.5).water(0x3938c9),
+    "mushroom_fields" => Climate::new(0.9, 1.0),
+    "nether_wastes" | "crimson_forest" | "warped_forest" | "soul_sand_valley" | "basalt_deltas" => Climate::new(2.0, 0.0),
+    "the_void" | "river" | "ocean" | "deep_ocean" | "lush_caves" => Climate::new(0.5, 0.5),
+    "the_end" | "end_highlands" | "end_midlands" | "small_end_islands" | "end_barrens" => Climate::new(0.5, 0.5),
+    // plains, beaches, dripstone caves and the deep dark
+    _ => Climate::new(0.8, 0.4)
+  }
+
+}
+
+/**
+ * get the name of a biome from the number chunks saved it as before 1.18. biomes that have been
+ * merged into others or renamed since are given their current name
+ */
+pub fn legacy_biome_name (id: i32) -> &'static str {
+
+  match id {
+    0 => "minecraft:ocean",
+    2 | 17 | 130 => "minecraft:desert",
+    3 | 20 => "minecraft:windswept_hills",
+    4 | 18 => "minecraft:forest",
+    5 | 19 | 133 => "minecraft:taiga",
+    6 | 134 => "minecraft:swamp",
+    7 => "minecraft:river",
+    8 => "minecraft:nether_wastes",
+    9 => "minecraft:the_end",
+    10 => "minecraft:frozen_ocean",
+    11 => "minecraft:frozen_river",
+    12 | 13 => "minecraft:snowy_plains",
+    14 | 15 => "minecraft:mushroom_fields",
+    16 => "minecraft:beach",
+    21 | 22 | 149 => "minecraft:jungle",
+    23 | 151 => "minecraft:sparse_jungle",
+    24 => "minecraft:deep_ocean",
+    25 => "minecraft:stony_shore",
+    26 => "minecraft:snowy_beach",
+    27 | 28 => "minecraft:birch_forest",
+    29 | 157 => "minecraft:dark_forest",
+    30 | 31 | 158 => "minecraft:snowy_taiga",
+    32 | 33 => "minecraft:old_growth_pine_taiga",
+    34 => "minecraft:windswept_forest",
+    35 => "minecraft:savanna",
+    36 => "minecraft:savanna_plateau",
+    37 | 39 | 167 => "minecraft:badlands",
+    38 | 166 => "minecraft:wooded_badlands",
+    40 => "minecraft:small_end_islands",
+    41 => "minecraft:end_midlands",
+    42 => "minecraft:end_highlands",
+    43 => "minecraft:end_barrens",
+    44 | 47 => "minecraft:warm_ocean",
+    45 | 48 => "minecraft:lukewarm_ocean",
+    46 | 49 => "minecraft:cold_ocean",
+    50 => "minecraft:deep_frozen_ocean",
+    127 => "minecraft:the_void",
+    129 => "minecraft:sunflower_plains",
+    131 | 162 => "minecraft:windswept_gravelly_hills",
+    132 => "minecraft:flower_forest",
+    140 => "minecraft:ice_spikes",
+    155 | 156 => "minecraft:old_growth_birch_forest",
+    160 | 161 => "minecraft:old_growth_spruce_taiga",
+    163 | 164 => "minecraft:windswept_savanna",
+    165 => "minecraft:eroded_badlands",
+    168 | 169 => "minecraft:bamboo_jungle",
+    170 => "minecraft:soul_sand_valley",
+    171 => "minecraft:crimson_forest",
+    172 => "minecraft:warped_forest",
+    173 => "minecraft:basalt_deltas",
+    174 => "minecraft:dripstone_caves",
+    175 => "minecraft:lush_caves",
+    _ => "minecraft:plains"
+  }
+
+}
+
+/**
+ * the colours grass, leaves and water are tinted in a biome, as 0xrrggbb
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeColours {
+  pub grass: u32,
+  pub foliage: u32,
+  pub water: u32
+}
+
+/**
+ * a biome found in the world
+ */
+#[derive(Clone, Debug)]
+pub struct Biome {
+  climate: Climate
+}
+
+impl Biome {
+
+  /**
+   * work out the colours of a biome, from the grass and foliage colormaps if there are any
+   */
+  pub fn colours (&self, grass_map: Option<&RgbaImage>, foliage_map: Option<&RgbaImage>) -> BiomeColours {
+
+    let climate = self.climate;
+    let grass = sample_colormap(grass_map, GRASS_CORNERS, climate);
+    let foliage = sample_colormap(foliage_map, FOLIAGE_CORNERS, climate);
+
+    let (grass, foliage) = match climate.grass {
+      GrassModifier::None => (grass, foliage),
+      // the average of the two colours, a channel at a time
+      GrassModifier::DarkForest => (((grass & 0xfefefe) + 0x28340a) >> 1, foliage),
+      GrassModifier::Fixed(grass, foliage) => (grass, foliage)
+    };
+
+    BiomeColours {
+      grass,
+      foliage,
+      water: climate.water
+    }
+
+  }
+
+}
+
+/**
+ * keeps track of the biomes found in the world, giving each a numeric id
+ */
+pub struct BiomeRegistry {
+  biomes: Vec<Biome>,
+  ids: HashMap<String, u16>
+}
+
+impl BiomeRegistry {
+
+  /**
+   * create a registry with only plains in it
+   */
+  pub fn new () -> BiomeRegistry {
+
+    let mut registry = BiomeRegistry {
+      biomes: vec![],
+      ids: HashMap::new()
+    };
+    registry.register("minecraft:plains");
+    registry
+
+  }
+
+  /**
+   * get the id of a biome by its namespaced name, registering it if it hasn't been seen before
+   */
+  pub fn register (&mut self, name: &str) -> u16 {
+
+    if let Some(id) = self.ids.get(name) {
+      return *id;
+    }
+
+    let id = self.biomes.len() as u16;
+    self.biomes.push(Biome {
+      climate: climate(name)
+    });
+    self.ids.insert(name.to_string(), id);
+    id
+
+  }
+
+  /**
+   * get a biome from its id
+   */
+  pub fn get (&self, id: u16) -> &Biome {
+    &self.biomes[id as usize]
+  }
+
+  /**
+   * get how many biomes have been registered
+   */
+  pub fn len (&self) -> usize {
+    self.biomes.len()
+  }
+
+}
+
+/**
+ * look up the colour for a climate in a colormap, which goes from hot on the left to cold on the
+ * right and from wet at the top to dry at the bottom. without a colormap, the colour is blended
+ * from the colours at its corners
+ */
+fn sample_colormap (colormap: Option<&RgbaImage>, corners: [u32; 3], climate: Climate) -> u32 {
+
+  // the colder it is, the less the downfall matters
+  let temperature = climate.temperature.clamp(0.0, 1.0);
+  let downfall = climate.downfall.clamp(0.0, 1.0) * temperature;
+
+  if let Some(colormap) = colormap.filter(|colormap| colormap.width() > 0 && colormap.height() > 0) {
+    let x = ((1.0 - temperature) * (colormap.width() - 1) as f32) as u32;
+    let y = ((1.0 - downfall) * (colormap.height() - 1) as f32) as u32;
+    let [r, g, b, _] = colormap.get_pixel(x, y).0;
+    return (r as u32) << 16 | (g as u32) << 8 | b as u32;
+  }
+
+  let weights = [downfall, temperature - downfall, 1.0 - temperature];
+  (0..3).rev().fold(0, |colour, channel| {
+    let value: f32 = corners.iter().zip(weights).map(|(corner, weight)| ((corner >> (channel * 8)) & 0xff) as f32 * weight).sum();
+    colour << 8 | value.round().clamp(0.0, 255.0) as u32
+  })
+
+}
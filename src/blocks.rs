@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+/*
+ * registry of the block types the renderer knows about, mapping palette names to numeric ids,
+ * and of the block states (blocks plus their properties) found in the world
+ */
+use crate::biomes::Tint;
+
+pub const AIR: u32 = 0;
+pub const UNKNOWN: u32 = 1;
+
+// the state id of plain air, which is always registered first
+pub const AIR_STATE: u32 = 0;
+
+// every known block, indexed by its id. new blocks must only ever be appended so ids stay stable
+const BLOCK_NAMES: &[&str] = &[
+  "air",
+  "unknown",
+  "stone",
+  "granite",
+  "polished_granite",
+  "diorite",
+  "polished_diorite",
+  "andesite",
+  "polished_andesite",
+  "deepslate",
+  "cobbled_deepslate",
+  "tuff",
+  "calcite",
+  "bedrock",
+  "grass_block",
+  "dirt",
+  "coarse_dirt",
+  "podzol",
+  "rooted_dirt",
+  "mud",
+  "mycelium",
+  "dirt_path",
+  "farmland",
+  "moss_block",
+  "cobblestone",
+  "mossy_cobblestone",
+  "sand",
+  "red_sand",
+  "gravel",
+  "clay",
+  "sandstone",
+  "red_sandstone",
+  "terracotta",
+  "snow",
+  "snow_block",
+  "ice",
+  "packed_ice",
+  "blue_ice",
+  "obsidian",
+  "netherrack",
+  "soul_sand",
+  "soul_soil",
+  "basalt",
+  "blackstone",
+  "glowstone",
+  "end_stone",
+  "water",
+  "lava",
+  "magma_block",
+  "coal_ore",
+  "deepslate_coal_ore",
+  "iron_ore",
+  "deepslate_iron_ore",
+  "copper_ore",
+  "deepslate_copper_ore",
+  "gold_ore",
+  "deepslate_gold_ore",
+  "redstone_ore",
+  "deepslate_redstone_ore",
+  "emerald_ore",
+  "deepslate_emerald_ore",
+  "lapis_ore",
+  "deepslate_lapis_ore",
+  "diamond_ore",
+  "deepslate_diamond_ore",
+  "oak_log",
+  "spruce_log",
+  "birch_log",
+  "jungle_log",
+  "acacia_log",
+  "dark_oak_log",
+  "mangrove_log",
+  "cherry_log",
+  "oak_planks",
+  "spruce_planks",
+  "birch_planks",
+  "jungle_planks",
+  "acacia_planks",
+  "dark_oak_planks",
+  "mangrove_planks",
+  "cherry_planks",
+  "oak_leaves",
+  "spruce_leaves",
+  "birch_leaves",
+  "jungle_leaves",
+  "acacia_leaves",
+  "dark_oak_leaves",
+  "mangrove_leaves",
+  "cherry_leaves",
+  "azalea_leaves",
+  "flowering_azalea_leaves",
+  "short_grass",
+  "tall_grass",
+  "fern",
+  "large_fern",
+  "dead_bush",
+  "dandelion",
+  "poppy",
+  "blue_orchid",
+  "allium",
+  "azure_bluet",
+  "oxeye_daisy",
+  "cornflower",
+  "lily_of_the_valley",
+  "sugar_cane",
+  "kelp",
+  "kelp_plant",
+  "seagrass",
+  "tall_seagrass",
+  "vine",
+  "glass",
+  "bricks",
+  "stone_bricks",
+  "mossy_stone_bricks",
+  "smooth_stone",
+  "bookshelf",
+  "crafting_table",
+  "furnace",
+  "chest",
+  "torch",
+  "wall_torch",
+  "oak_slab",
+  "stone_slab",
+  "cobblestone_slab",
+  "oak_stairs",
+  "stone_stairs",
+  "cobblestone_stairs",
+  "oak_fence",
+  "glass_pane",
+  "white_wool",
+  "coal_block",
+  "iron_block",
+  "copper_block",
+  "gold_block",
+  "redstone_block",
+  "emerald_block",
+  "lapis_block",
+  "diamond_block",
+  "amethyst_block",
+  "budding_amethyst",
+  "dripstone_block",
+  "pointed_dripstone",
+  "sculk",
+  "pumpkin",
+  "melon",
+  "cactus",
+  "bamboo",
+  "spawner"
+];
+
+// blocks that can be seen through, so the faces of blocks behind them still need drawing
+const TRANSPARENT_BLOCKS: &[&str] = &[
+  "air",
+  "unknown", // anything not listed, like stained glass, might be see-through
+  "water",
+  "ice",
+  "glass",
+  "glass_pane",
+  "oak_leaves",
+  "spruce_leaves",
+  "birch_leaves",
+  "jungle_leaves",
+  "acacia_leaves",
+  "dark_oak_leaves",
+  "mangrove_leaves",
+  "cherry_leaves",
+  "azalea_leaves",
+  "flowering_azalea_leaves",
+  "short_grass",
+  "tall_grass",
+  "fern",
+  "large_fern",
+  "dead_bush",
+  "dandelion",
+  "poppy",
+  "blue_orchid",
+  "allium",
+  "azure_bluet",
+  "oxeye_daisy",
+  "cornflower",
+  "lily_of_the_valley",
+  "sugar_cane",
+  "kelp",
+  "kelp_plant",
+  "seagrass",
+  "tall_seagrass",
+  "vine",
+  "torch",
+  "wall_torch",
+  "oak_slab",
+  "stone_slab",
+  "cobblestone_slab",
+  "oak_stairs",
+  "stone_stairs",
+  "cobblestone_stairs",
+  "oak_fence",
+  "snow",
+  "farmland",
+  "dirt_path",
+  "chest",
+  "cactus",
+  "bamboo",
+  "pointed_dripstone",
+  "spawner"
+];
+
+/**
+ * lookup table from block name to id, built the first time it's needed
+ */
+fn ids () -> &'static HashMap<&'static str, u32> {
+
+  static IDS: OnceLock<HashMap<&'static str, u32>> = OnceLock::new();
+  IDS.get_or_init(|| {
+    BLOCK_NAMES.iter().enumerate().map(|(id, name)| (*name, id as u32)).collect()
+  })
+
+}
+
+/**
+ * get the id of a block from its namespaced palette name, like minecraft:stone
+ */
+pub fn block_id (name: &str) -> u32 {
+
+  // the other air blocks are drawn the same as regular air
+  if is_air(name) {
+    return AIR;
+  }
+
+  // only vanilla blocks are known, anything from a mod or datapack is unknown
+  let Some(key) = name.strip_prefix("minecraft:") else {
+    return UNKNOWN;
+  };
+
+  ids().get(key).copied().unwrap_or(UNKNOWN)
+
+}
+
+/**
+ * get the name of a block from its id
+ */
+pub fn block_name (id: u32) -> &'static str {
+  BLOCK_NAMES.get(id as usize).copied().unwrap_or("unknown")
+}
+
+/**
+ * check whether blocks behind a block can be seen through it
+ */
+pub fn is_transparent (id: u32) -> bool {
+
+  static TRANSPARENT: OnceLock<Vec<bool>> = OnceLock::new();
+  let transparent = TRANSPARENT.get_or_init(|| {
+    BLOCK_NAMES.iter().map(|name| TRANSPARENT_BLOCKS.contains(name)).collect()
+  });
+
+  transparent.get(id as usize).copied().unwrap_or(false)
+
+}
+
+/**
+ * get the colour a block is tinted. grass, most leaves and water take on the colour of the biome
+ * they're in
+ */
+pub fn tint (id: u32) -> Tint {
+
+  match block_name(id) {
+    "grass_block" | "short_grass" | "tall_grass" | "fern" | "large_fern" | "sugar_cane" => Tint::Grass,
+    "oak_leaves" | "jungle_leaves" | "acacia_leaves" | "dark_oak_leaves" | "vine" => Tint::Foliage,
+    "water" => Tint::Water,
+    "spruce_leaves" => Tint::Fixed(0x619961),
+    "birch_leaves" => Tint::Fixed(0x80a755),
+    "mangrove_leaves" => Tint::Fixed(0x92c648),
+    _ => Tint::None
+  }
+
+}
+
+/**
+ * check whether a block is one of the air blocks
+ */
+fn is_air (name: &str) -> bool {
+  matches!(name, "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air")
+}
+
+/**
+ * a block along with its properties, like which way it's facing or whether it's waterlogged
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockState {
+  pub id: u32,
+  pub name: String,
+  pub properties: BTreeMap<String, String>
+}
+
+impl BlockState {
+
+  /**
+   * create a block state from a block's name and properties, like an entry in a section palette.
+   * names without a namespace are taken to be vanilla blocks
+   */
+  pub fn new (name: &str, properties: BTreeMap<String, String>) -> BlockState {
+
+    let name = if name.contains(':') {
+      name.to_string()
+    } else {
+      format!("minecraft:{}", name)
+    };
+
+    BlockState {
+      id: block_id(&name),
+      name,
+      properties
+    }
+
+  }
+
+}
+
+/**
+ * gives each distinct block state in the world a compact id
+ */
+pub struct StateRegistry {
+  states: Vec<BlockState>,
+  ids: HashMap<BlockState, u32>
+}
+
+impl StateRegistry {
+
+  /**
+   * create a registry containing only air
+   */
+  pub fn new () -> StateRegistry {
+
+    let air = BlockState {
+      id: AIR,
+      name: String::from("minecraft:air"),
+      properties: BTreeMap::new()
+    };
+
+    StateRegistry {
+      states: vec![air.clone()],
+      ids: HashMap::from([(air, AIR_STATE)])
+    }
+
+  }
+
+  /**
+   * get the id of a block state, registering it if it hasn't been seen before
+   */
+  pub fn register (&mut self, state: BlockState) -> u32 {
+
+    // all the air blocks share the one air state
+    if state.id == AIR {
+      return AIR_STATE;
+    }
+
+    if let Some(id) = self.ids.get(&state) {
+      return *id;
+    }
+
+    let id = self.states.len() as u32;
+    self.ids.insert(state.clone(), id);
+    self.states.push(state);
+    id
+
+  }
+
+  /**
+   * get a block state from its id
+   */
+  pub fn get (&self, state: u32) -> &BlockState {
+    &self.states[state as usize]
+  }
+
+  /**
+   * the number of registered block states, so ids run from 0 up to this
+   */
+  pub fn len (&self) -> usize {
+    self.states.len()
+  }
+
+  /**
+   * check whether there are no block states, which never happens since air is always registered
+   */
+  #[allow(dead_code)]
+  pub fn is_empty (&self) -> bool {
+    self.states.is_empty()
+  }
+
+}
@@ -1,25 +1,81 @@
 use std::f32::consts::PI;
+use std::time::Instant;
 
 /*
- * provides a struct managing the camera
+ * provides a struct managing the camera. positions are in blocks, the same as the coordinates
+ * shown in game, so a block at (x, y, z) fills the space up to (x + 1, y + 1, z + 1)
  */
-#[rustfmt::skip]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-  1.0, 0.0, 0.0, 0.0,
-  0.0, 1.0, 0.0, 0.0,
-  0.0, 0.0, 0.5, 0.5,
-  0.0, 0.0, 0.0, 1.0,
-);
+use cgmath::{EuclideanSpace, InnerSpace};
+use winit::keyboard::KeyCode;
+
+
+// how fast the camera flies, in blocks per second
+const FLY_SPEED: f32 = 10.0;
+// how far the camera turns for each pixel the mouse moves, in radians
+const MOUSE_SENSITIVITY: f32 = 0.002;
+// stop just short of looking straight up or down, where the view would flip over
+const PITCH_LIMIT: f32 = PI / 2.0 - 0.01;
+// how far the orbit target moves for each pixel the mouse is dragged, relative to its distance
+const PAN_SENSITIVITY: f32 = 0.0015;
+// how much closer each step of the scroll wheel brings the orbit camera
+const ZOOM_STEP: f32 = 0.9;
+// how far above the top of the world or below the bottom the camera can go, in blocks
+const HEIGHT_MARGIN: f32 = 64.0;
+
+/**
+ * how the camera moves
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+  // turn, pan and zoom around a target point with the mouse
+  Orbit,
+  // fly around freely, steered with the keyboard and mouse
+  Fly
+}
+
+/**
+ * how the view is projected onto the screen. depth is always reversed, with the near plane at 1
+ * and the far plane at 0, which spreads float precision evenly over long distances
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Projection {
+  // things get smaller further away, up to the far plane
+  Perspective,
+  // a perspective without a far plane, so nothing is ever clipped for being too far away
+  InfinitePerspective,
+  // things stay the same size however far away they are, for map views
+  Orthographic
+}
+
+/**
+ * which movement keys are currently held down
+ */
+#[derive(Default)]
+struct Controls {
+  forward: bool,
+  back: bool,
+  left: bool,
+  right: bool,
+  up: bool,
+  down: bool
+}
 
 pub struct Camera {
   eye: cgmath::Point3<f32>,
   target: cgmath::Point3<f32>,
   up: cgmath::Vector3<f32>,
+  yaw: f32, // radians, 0 looking along +x
+  pitch: f32, // radians, positive looking up
+  distance: f32, // how far the orbit camera is from its target, in blocks
+  pub mode: CameraMode,
+  controls: Controls,
+  pub projection: Projection,
   aspect: f32,
-  fovy: f32,
+  fovy: f32, // degrees
   znear: f32,
   zfar: f32,
-  create_time: std::time::SystemTime
+  height_limits: (f32, f32), // the lowest and highest y the camera can focus on
+  last_update: Instant
 }
 
 impl Camera {
@@ -30,18 +86,24 @@ impl Camera {
   pub fn new() -> Camera {
 
     Camera {
-      // position the camera 1 unit up and 2 units back
-      // +z is out of the screen
-      eye: (0.0, 1.0, 2.0).into(),
-      // have it look at the origin
-      target: (16.0, -128.0, 16.0).into(),
+      // the eye is placed around the target when the camera updates
+      eye: (0.0, 0.0, 0.0).into(),
+      target: (0.0, 0.0, 0.0).into(),
       // which way is "up"
       up: cgmath::Vector3::unit_y(),
+      // look down at the target from a corner
+      yaw: PI / 4.0,
+      pitch: -PI / 6.0,
+      distance: 16.0,
+      mode: CameraMode::Orbit,
+      controls: Controls::default(),
+      projection: Projection::Perspective,
       aspect: 400.0 / 300.0,
       fovy: 45.0,
-      znear: 0.1,
-      zfar: 100.0,
-      create_time: std::time::SystemTime::now()
+      znear: 0.05,
+      zfar: 500.0,
+      height_limits: (f32::NEG_INFINITY, f32::INFINITY),
+      last_update: Instant::now()
     }
 
   }
@@ -54,18 +116,254 @@ impl Camera {
   }
 
   /**
-   * make the camera spin around the origin
+   * set the vertical field of view in degrees. orthographic views use it too, to decide how much
+   * they show at the orbit distance
+   */
+  pub fn set_fovy(&mut self, fovy: f32) {
+    self.fovy = fovy.clamp(1.0, 170.0);
+  }
+
+  /**
+   * set the distances to the near and far clipping planes. the far plane is ignored by infinite
+   * perspective
+   */
+  pub fn set_clip_planes(&mut self, znear: f32, zfar: f32) {
+    self.znear = znear.max(0.001);
+    self.zfar = zfar.max(self.znear * 2.0);
+  }
+
+  /**
+   * get the vertical field of view in degrees
+   */
+  pub fn fovy(&self) -> f32 {
+    self.fovy
+  }
+
+  /**
+   * get the distances to the near and far clipping planes
+   */
+  pub fn clip_planes(&self) -> (f32, f32) {
+    (self.znear, self.zfar)
+  }
+
+  /**
+   * switch to the next projection, from perspective to infinite perspective to orthographic
+   */
+  pub fn cycle_projection(&mut self) {
+    self.projection = match self.projection {
+      Projection::Perspective => Projection::InfinitePerspective,
+      Projection::InfinitePerspective => Projection::Orthographic,
+      Projection::Orthographic => Projection::Perspective
+    };
+  }
+
+  /**
+   * look at the orbit target from a fixed angle with an orthographic projection, like a map. a
+   * pitch of -90 degrees looks straight down, and the limit stops just short of it
+   */
+  pub fn set_map_view(&mut self, yaw: f32, pitch: f32) {
+
+    if self.mode == CameraMode::Fly {
+      self.toggle_mode();
+    }
+    self.projection = Projection::Orthographic;
+    self.yaw = yaw;
+    self.pitch = pitch.clamp(-PITCH_LIMIT, PITCH_LIMIT);
+
+  }
+
+  /**
+   * keep the camera within a margin of the range of y a world's blocks can be in, so it can't get
+   * lost in the empty space above or below it
+   */
+  pub fn set_height_limits (&mut self, min_y: f32, max_y: f32) {
+    self.height_limits = (min_y - HEIGHT_MARGIN, max_y + HEIGHT_MARGIN);
+  }
+
+  /**
+   * start flying from a position, in block coordinates
+   */
+  pub fn fly_to(&mut self, eye: [f32; 3]) {
+    self.mode = CameraMode::Fly;
+    self.eye = eye.into();
+  }
+
+  /**
+   * look in a direction given the way the game shows it, in degrees. a yaw of 0 faces south (+z)
+   * and 90 faces west, and a positive pitch looks down
+   */
+  pub fn set_game_rotation(&mut self, yaw: f32, pitch: f32) {
+    self.yaw = (yaw + 90.0).to_radians();
+    self.pitch = (-pitch.to_radians()).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+  }
+
+  /**
+   * switch between orbiting and flying. both look the same way, so the view doesn't jump, and the
+   * orbit camera picks a new target in front of wherever the fly camera ended up
+   */
+  pub fn toggle_mode(&mut self) {
+
+    self.mode = match self.mode {
+      CameraMode::Orbit => CameraMode::Fly,
+      CameraMode::Fly => {
+        self.target = self.eye + self.direction() * self.distance;
+        CameraMode::Orbit
+      }
+    };
+    self.controls = Controls::default();
+
+  }
+
+  /**
+   * point the orbit camera at the middle of a box, just far enough away that all of it fits on
+   * screen
+   */
+  pub fn frame_box(&mut self, min: [f32; 3], max: [f32; 3]) {
+
+    let min = cgmath::Point3::from(min);
+    let max = cgmath::Point3::from(max);
+    let radius = (max - min).magnitude() / 2.0;
+
+    // fit the box's bounding sphere into whichever of the width or height is narrower
+    let half_fovy = (self.fovy / 2.0).to_radians();
+    let half_fov = half_fovy.min((half_fovy.tan() * self.aspect).atan());
+
+    self.mode = CameraMode::Orbit;
+    self.target = cgmath::Point3::midpoint(min, max);
+    self.distance = (radius / half_fov.sin()).max(1.0);
+
+  }
+
+  /**
+   * get the point the camera is focused on, the target when orbiting or the eye when flying
+   */
+  pub fn focus(&self) -> [f32; 3] {
+    match self.mode {
+      CameraMode::Orbit => self.target.into(),
+      CameraMode::Fly => self.eye.into()
+    }
+  }
+
+  /**
+   * turn the orbit camera around its target by how far the mouse was dragged, in pixels
+   */
+  pub fn orbit(&mut self, dx: f64, dy: f64) {
+    self.turn(dx, dy);
+  }
+
+  /**
+   * move the orbit camera's target across the screen by how far the mouse was dragged, in pixels
+   */
+  pub fn pan(&mut self, dx: f64, dy: f64) {
+
+    let direction = self.direction();
+    let right = direction.cross(self.up).normalize();
+    let up = right.cross(direction);
+
+    // the further away the target is, the more it moves, so it keeps up with the mouse
+    let scale = self.distance * PAN_SENSITIVITY;
+    self.target += (up * dy as f32 - right * dx as f32) * scale;
+
+  }
+
+  /**
+   * move the orbit camera towards or away from its target by a number of scroll wheel steps
+   */
+  pub fn zoom(&mut self, steps: f32) {
+    self.distance = (self.distance * ZOOM_STEP.powf(steps)).max(1.0);
+  }
+
+  /**
+   * press or release a movement key, returning whether the key moves the camera
+   */
+  pub fn process_key(&mut self, key: KeyCode, pressed: bool) -> bool {
+
+    let control = match key {
+      KeyCode::KeyW => &mut self.controls.forward,
+      KeyCode::KeyS => &mut self.controls.back,
+      KeyCode::KeyA => &mut self.controls.left,
+      KeyCode::KeyD => &mut self.controls.right,
+      KeyCode::Space => &mut self.controls.up,
+      KeyCode::ShiftLeft | KeyCode::ShiftRight => &mut self.controls.down,
+      _ => return false
+    };
+    *control = pressed;
+    true
+
+  }
+
+  /**
+   * let go of every key, for when the window loses focus and releases would be missed
+   */
+  pub fn release_keys(&mut self) {
+    self.controls = Controls::default();
+  }
+
+  /**
+   * turn the fly camera by how far the mouse moved, in pixels
+   */
+  pub fn process_mouse(&mut self, dx: f64, dy: f64) {
+
+    if self.mode != CameraMode::Fly {
+      return;
+    }
+    self.turn(dx, dy);
+
+  }
+
+  /**
+   * change the direction the camera looks by how far the mouse moved, in pixels
+   */
+  fn turn(&mut self, dx: f64, dy: f64) {
+
+    self.yaw += dx as f32 * MOUSE_SENSITIVITY;
+    self.pitch = (self.pitch - dy as f32 * MOUSE_SENSITIVITY).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+
+  }
+
+  /**
+   * move the camera by however much time has passed since the last update
    */
   pub fn update(&mut self) {
-    let radius = 32.0;
-    let time = std::time::SystemTime::now()
-      .duration_since(self.create_time)
-      .unwrap()
-      .as_millis() as f32 / 50.0;
-    let x = (time * PI / 180.0).sin() * radius;
-    let z = (time * PI / 180.0).cos() * radius;
 
-    self.eye = cgmath::Point3::new(x + 16.0, -120.0, z + 16.0);
+    // a long pause, like while the window is being dragged, shouldn't send the camera flying
+    let now = Instant::now();
+    let dt = now.duration_since(self.last_update).as_secs_f32().min(0.1);
+    self.last_update = now;
+
+    let (min_y, max_y) = self.height_limits;
+    match self.mode {
+      CameraMode::Orbit => {
+        // sit back from the target, looking straight at it
+        self.target.y = self.target.y.clamp(min_y, max_y);
+        self.eye = self.target - self.direction() * self.distance;
+      }
+      CameraMode::Fly => {
+        // walk along the ground whichever way the camera is pitched, and rise and fall straight up
+        let forward = cgmath::Vector3::new(self.yaw.cos(), 0.0, self.yaw.sin());
+        let right = cgmath::Vector3::new(-self.yaw.sin(), 0.0, self.yaw.cos());
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+
+        let velocity = forward * axis(self.controls.forward, self.controls.back)
+          + right * axis(self.controls.right, self.controls.left)
+          + self.up * axis(self.controls.up, self.controls.down);
+
+        self.eye += velocity * FLY_SPEED * dt;
+        self.eye.y = self.eye.y.clamp(min_y, max_y);
+      }
+    }
+
+  }
+
+  /**
+   * get the direction the fly camera is looking in
+   */
+  fn direction(&self) -> cgmath::Vector3<f32> {
+    cgmath::Vector3::new(
+      self.pitch.cos() * self.yaw.cos(),
+      self.pitch.sin(),
+      self.pitch.cos() * self.yaw.sin()
+    )
   }
 
   /**
@@ -73,10 +371,76 @@ impl Camera {
    */
   pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
 
-    let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-    let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+    let view = match self.mode {
+      CameraMode::Orbit => cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up),
+      CameraMode::Fly => cgmath::Matrix4::look_to_rh(self.eye, self.direction(), self.up)
+    };
+    self.projection_matrix() * view
+
+  }
+
+  /**
+   * calculate the projection matrix, straight into wgpu clip space with reversed depth
+   */
+  #[rustfmt::skip]
+  fn projection_matrix(&self) -> cgmath::Matrix4<f32> {
+
+    let (n, f) = (self.znear, self.zfar);
+    let focal = 1.0 / (self.fovy.to_radians() / 2.0).tan();
+
+    // the matrices are written out a column at a time
+    match self.projection {
+      Projection::Perspective => cgmath::Matrix4::new(
+        focal / self.aspect, 0.0, 0.0, 0.0,
+        0.0, focal, 0.0, 0.0,
+        0.0, 0.0, n / (f - n), -1.0,
+        0.0, 0.0, n * f / (f - n), 0.0
+      ),
+      // the same as perspective with the far plane pushed out to infinity
+      Projection::InfinitePerspective => cgmath::Matrix4::new(
+        focal / self.aspect, 0.0, 0.0, 0.0,
+        0.0, focal, 0.0, 0.0,
+        0.0, 0.0, 0.0, -1.0,
+        0.0, 0.0, n, 0.0
+      ),
+      // show as much as the perspective would at the orbit distance, and everything from the far
+      // plane behind the eye to the far plane in front, since there's no perspective to get wrong
+      Projection::Orthographic => {
+        let half_height = self.distance / focal;
+        let half_width = half_height * self.aspect;
+        let depth = f * 2.0;
+        cgmath::Matrix4::new(
+          1.0 / half_width, 0.0, 0.0, 0.0,
+          0.0, 1.0 / half_height, 0.0, 0.0,
+          0.0, 0.0, 1.0 / depth, 0.0,
+          0.0, 0.0, f / depth, 1.0
+        )
+      }
+    }
+
+  }
+
+  /**
+   * get the planes bounding what the camera can see, as (a, b, c, d) where ax + by + cz + d >= 0
+   * for points inside
+   */
+  pub fn frustum_planes(&self) -> [[f32; 4]; 6] {
 
-    return OPENGL_TO_WGPU_MATRIX * proj * view;
+    let m = self.build_view_projection_matrix();
+    let row = |i: usize| [m.x[i], m.y[i], m.z[i], m.w[i]];
+    let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+    let add = |a: [f32; 4], b: [f32; 4]| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]];
+    let sub = |a: [f32; 4], b: [f32; 4]| [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]];
+
+    // wgpu clip space has x and y from -w to w, but z from 0 to w
+    [
+      add(r3, r0),
+      sub(r3, r0),
+      add(r3, r1),
+      sub(r3, r1),
+      r2,
+      sub(r3, r2)
+    ]
 
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,658 @@
+use std::collections::{BTreeMap, HashMap};
+
+/*
+ * reads the nbt of a chunk in each of the layouts the game has saved chunks in over the years,
+ * and turns it into the same list of sections with a palette of block states. the layout is
+ * worked out from the chunk's DataVersion
+ */
+use crate::biomes;
+use crate::blocks::BlockState;
+use crate::legacy;
+use crate::world::WorldError;
+use fastnbt::{ByteArray, LongArray, Value};
+use serde::de::Error;
+use serde::Deserialize;
+
+// 17w47a, the 1.13 snapshot that replaced numbered blocks with names and properties
+const FLATTENING_VERSION: i32 = 1444;
+// 20w17a, the 1.16 snapshot that stopped block indices spanning two longs
+const ALIGNED_INDICES_VERSION: i32 = 2527;
+// 21w43a, the 1.18 snapshot that moved everything out of the Level compound
+const NO_LEVEL_VERSION: i32 = 2844;
+
+/**
+ * a chunk in any of the layouts, since each keeps to its own field names
+ */
+#[derive(Deserialize)]
+struct ChunkNbt {
+  // missing from chunks saved before 1.9
+  #[serde(rename = "DataVersion")]
+  data_version: Option<i32>,
+  // 1.18 onwards
+  #[serde(rename = "xPos")]
+  x_pos: Option<i32>,
+  #[serde(rename = "zPos")]
+  z_pos: Option<i32>,
+  sections: Option<Vec<SectionNbt>>,
+  // whether the light saved with the chunk is finished, since 1.14
+  #[serde(rename = "isLightOn")]
+  is_light_on: Option<bool>,
+  // before 1.18
+  #[serde(rename = "Level")]
+  level: Option<LevelNbt>
+}
+
+#[derive(Deserialize)]
+struct SectionNbt {
+  #[serde(rename = "Y")]
+  y: i8,
+  block_states: Option<BlockStatesNbt>,
+  biomes: Option<BiomesNbt>,
+  #[serde(rename = "BlockLight")]
+  block_light: Option<ByteArray>,
+  #[serde(rename = "SkyLight")]
+  sky_light: Option<ByteArray>
+}
+
+#[derive(Deserialize)]
+struct BlockStatesNbt {
+  palette: Vec<PaletteEntry>,
+  data: Option<LongArray>
+}
+
+#[derive(Deserialize)]
+struct BiomesNbt {
+  palette: Vec<String>,
+  data: Option<LongArray>
+}
+
+#[derive(Deserialize)]
+struct PaletteEntry {
+  #[serde(rename = "Name")]
+  name: String,
+  #[serde(rename = "Properties", default)]
+  properties: BTreeMap<String, String>
+}
+
+#[derive(Deserialize)]
+struct LevelNbt {
+  #[serde(rename = "xPos")]
+  x_pos: i32,
+  #[serde(rename = "zPos")]
+  z_pos: i32,
+  #[serde(rename = "Sections", default)]
+  sections: Vec<LevelSectionNbt>,
+  #[serde(rename = "isLightOn")]
+  is_light_on: Option<bool>,
+  // numbered biomes, an int array since 1.13 and a byte array before
+  #[serde(rename = "Biomes")]
+  biomes: Option<Value>
+}
+
+#[derive(Deserialize)]
+struct LevelSectionNbt {
+  #[serde(rename = "Y")]
+  y: i8,
+  // 1.13 to 1.17
+  #[serde(rename = "Palette")]
+  palette: Option<Vec<PaletteEntry>>,
+  #[serde(rename = "BlockStates")]
+  block_states: Option<LongArray>,
+  // before 1.13, a byte of block id and half a byte each of extra id bits and data for each block
+  #[serde(rename = "Blocks")]
+  blocks: Option<ByteArray>,
+  #[serde(rename = "Add")]
+  add: Option<ByteArray>,
+  #[serde(rename = "Data")]
+  data: Option<ByteArray>,
+  #[serde(rename = "BlockLight")]
+  block_light: Option<ByteArray>,
+  #[serde(rename = "SkyLight")]
+  sky_light: Option<ByteArray>
+}
+
+/**
+ * a chunk read from its nbt, in the same form whichever layout it was saved in
+ */
+pub struct ChunkData {
+  pub x: i32,
+  pub z: i32,
+  pub data_version: i32, // the version of the game that saved the chunk, 0 if it was before 1.9
+  pub sections: Vec<SectionData>
+}
+
+pub struct SectionData {
+  pub y: i32,
+  pub blocks: Option<SectionBlocks>, // None if the section has no blocks saved, like ones above and below the world that only hold light
+  pub biomes: Option<SectionBiomes>,
+  pub light: SectionLightData
+}
+
+/**
+ * the blocks in a section, as indices into a palette of block states
+ */
+pub struct SectionBlocks {
+  pub palette: Vec<BlockState>,
+  pub indices: Option<Vec<u16>> // for each block [y][z][x], or None if they're all the first palette entry
+}
+
+/**
+ * the biomes in a section, as indices into a palette of biome names. each biome covers a 4x4x4
+ * cell of blocks
+ */
+pub struct SectionBiomes {
+  pub palette: Vec<String>,
+  pub indices: Option<Vec<u8>> // for each cell [y][z][x], or None if they're all the first palette entry
+}
+
+/**
+ * the light levels in a section, as half a byte for each block [y][z][x] with the lower half first.
+ * either can be None if the section has none saved
+ */
+pub struct SectionLightData {
+  pub block: Option<Vec<u8>>,
+  pub sky: Option<Vec<u8>>
+}
+
+/**
+ * read a chunk from its decompressed nbt
+ */
+pub fn parse (bytes: &[u8]) -> Result<ChunkData, WorldError> {
+
+  let chunk: ChunkNbt = fastnbt::from_bytes(bytes)?;
+  let version = chunk.data_version.unwrap_or(0);
+
+  match chunk.level {
+    Some(level) if version < NO_LEVEL_VERSION => {
+      let biomes: Vec<i32> = match level.biomes {
+        Some(Value::IntArray(biomes)) => biomes.to_vec(),
+        Some(Value::ByteArray(biomes)) => biomes.iter().map(|biome| *biome as u8 as i32).collect(),
+        _ => vec![]
+      };
+      let lit = level.is_light_on.unwrap_or(true);
+      Ok(ChunkData {
+        x: level.x_pos,
+        z: level.z_pos,
+        data_version: version,
+        // sections without any blocks, which only hold light, are all air
+        sections: level.sections.into_iter().map(|section| SectionData {
+          y: section.y as i32,
+          blocks: Some(if version >= FLATTENING_VERSION {
+            palette_section(section.palette, section.block_states.as_deref(), version >= ALIGNED_INDICES_VERSION)
+          } else {
+            numbered_section(&section)
+          }),
+          biomes: numbered_biomes(&biomes, section.y as i32),
+          light: section_light(lit, section.block_light, section.sky_light)
+        }).collect()
+      })
+    }
+    _ => {
+      let missing = |field| WorldError::Nbt(fastnbt::error::Error::missing_field(field));
+      let sections = chunk.sections.ok_or_else(|| missing("sections"))?;
+      let lit = chunk.is_light_on.unwrap_or(true);
+      Ok(ChunkData {
+        x: chunk.x_pos.ok_or_else(|| missing("xPos"))?,
+        z: chunk.z_pos.ok_or_else(|| missing("zPos"))?,
+        data_version: version,
+        sections: sections.into_iter().map(|section| SectionData {
+          y: section.y as i32,
+          blocks: section.block_states.map(|states| palette_section(Some(states.palette), states.data.as_deref(), true)),
+          biomes: section.biomes.map(|biomes| {
+            let indices = biomes.data.map(|data| unpack_biomes(&data, biomes.palette.len()));
+            SectionBiomes {
+              palette: biomes.palette,
+              indices
+            }
+          }),
+          light: section_light(lit, section.block_light, section.sky_light)
+        }).collect()
+      })
+    }
+  }
+
+}
+
+/**
+ * read the light saved in a section. chunks whose light hasn't been worked out yet are saved with
+ * light the game throws away, so it's left out here too
+ */
+fn section_light (lit: bool, block: Option<ByteArray>, sky: Option<ByteArray>) -> SectionLightData {
+
+  let nibbles = |array: Option<ByteArray>| array.filter(|_| lit).map(|array| array.iter().map(|byte| *byte as u8).collect());
+
+  SectionLightData {
+    block: nibbles(block),
+    sky: nibbles(sky)
+  }
+
+}
+
+/**
+ * read the blocks of a section saved as a palette and indices into it packed into longs. before
+ * 1.16 the indices ran straight on from one long to the next, rather than each long holding a
+ * whole number of them
+ */
+fn palette_section (palette: Option<Vec<PaletteEntry>>, data: Option<&[i64]>, aligned: bool) -> SectionBlocks {
+
+  let Some(palette) = palette else {
+    return air_section();
+  };
+  let palette: Vec<BlockState> = palette.into_iter()
+    .map(|entry| BlockState::new(&entry.name, entry.properties))
+    .collect();
+
+  // a section without data is filled entirely with the first palette entry
+  let indices = data.map(|data| if aligned {
+    unpack_block_states(data, palette.len())
+  } else {
+    unpack_spanning_block_states(data, palette.len())
+  });
+
+  SectionBlocks {
+    palette,
+    indices
+  }
+
+}
+
+/**
+ * read the blocks of a section saved as numbers, making a palette of the different blocks in it
+ */
+fn numbered_section (section: &LevelSectionNbt) -> SectionBlocks {
+
+  let Some(blocks) = section.blocks.as_deref() else {
+    return air_section();
+  };
+  // the extra bits of each block are split into halves of a byte, the lower half first
+  let nibble = |array: &Option<ByteArray>, i: usize| {
+    array.as_deref().and_then(|array| array.get(i / 2)).map(|byte| (*byte as u8 >> ((i % 2) * 4)) & 15).unwrap_or(0)
+  };
+
+  let mut palette = vec![];
+  let mut palette_indices: HashMap<(u16, u8), u16> = HashMap::new();
+
+  let indices = (0..4096).map(|i| {
+    let id = blocks.get(i).map(|id| *id as u8 as u16).unwrap_or(0) | (nibble(&section.add, i) as u16) << 8;
+    let data = nibble(&section.data, i);
+    *palette_indices.entry((id, data)).or_insert_with(|| {
+      palette.push(legacy::legacy_state(id, data));
+      (palette.len() - 1) as u16
+    })
+  }).collect();
+
+  SectionBlocks {
+    palette,
+    indices: Some(indices)
+  }
+
+}
+
+/**
+ * create a section that's entirely air
+ */
+fn air_section () -> SectionBlocks {
+  SectionBlocks {
+    palette: vec![BlockState::new("air", BTreeMap::new())],
+    indices: None
+  }
+}
+
+/**
+ * read the biomes of a section from the numbered biomes of the whole chunk, saved before 1.18.
+ * since 1.15 there's a biome for each cell from y 0 up, and before that one for each column of
+ * blocks, where the column in the middle of each cell is used
+ */
+fn numbered_biomes (biomes: &[i32], section_y: i32) -> Option<SectionBiomes> {
+
+  let ids: Vec<i32> = if biomes.len() == 256 {
+    (0..64).map(|cell| biomes[((cell >> 2 & 3) * 4 + 2) * 16 + (cell & 3) * 4 + 2]).collect()
+  } else {
+    let start = usize::try_from(section_y).ok()? * 64;
+    biomes.get(start..start + 64)?.to_vec()
+  };
+
+  let mut palette = vec![];
+  let mut palette_indices: HashMap<i32, u8> = HashMap::new();
+
+  let indices = ids.into_iter().map(|id| {
+    *palette_indices.entry(id).or_insert_with(|| {
+      palette.push(biomes::legacy_biome_name(id).to_string());
+      (palette.len() - 1) as u8
+    })
+  }).collect();
+
+  Some(SectionBiomes {
+    palette,
+    indices: Some(indices)
+  })
+
+}
+
+/**
+ * unpack the palette indices of a section's biomes. like block states they never span two longs,
+ * but they only take as many bits as the palette needs, with no minimum
+ */
+fn unpack_biomes (data: &[i64], palette_len: usize) -> Vec<u8> {
+
+  let bits = (usize::BITS - palette_len.saturating_sub(1).leading_zeros()).max(1) as usize;
+  let per_long = 64 / bits;
+  let mask = (1u64 << bits) - 1;
+
+  (0..64).map(|i| {
+    let Some(long) = data.get(i / per_long) else {
+      return 0;
+    };
+    ((*long as u64 >> ((i % per_long) * bits)) & mask) as u8
+  }).collect()
+
+}
+
+/**
+ * get how many bits each palette index takes up, which is at least 4
+ */
+fn index_bits (palette_len: usize) -> usize {
+  (usize::BITS - palette_len.saturating_sub(1).leading_zeros()).max(4) as usize
+}
+
+/**
+ * unpack the palette indices of a section from its packed long array
+ *
+ * each long holds as many indices as fit in 64 bits, with at least 4 bits per index. indices
+ * never span two longs, so any leftover high bits in a long are padding
+ */
+fn unpack_block_states (data: &[i64], palette_len: usize) -> Vec<u16> {
+
+  let bits = index_bits(palette_len);
+  let per_long = 64 / bits;
+  let mask = (1u64 << bits) - 1;
+
+  (0..4096).map(|i| {
+    // sections with a truncated data array are treated as air past the end
+    let Some(long) = data.get(i / per_long) else {
+      return 0;
+    };
+    ((*long as u64 >> ((i % per_long) * bits)) & mask) as u16
+  }).collect()
+
+}
+
+/**
+ * unpack the palette indices of a section saved before 1.16, where the indices are packed one
+ * after another and some are split between the end of one long and the start of the next
+ */
+fn unpack_spanning_block_states (data: &[i64], palette_len: usize) -> Vec<u16> {
+
+  let bits = index_bits(palette_len);
+  let mask = (1u64 << bits) - 1;
+
+  (0..4096).map(|i| {
+    let (long, offset) = ((i * bits) / 64, (i * bits) % 64);
+    let Some(low) = data.get(long) else {
+      return 0;
+    };
+    let mut value = *low as u64 >> offset;
+    if offset + bits > 64 {
+      value |= (data.get(long + 1).copied().unwrap_or(0) as u64) << (64 - offset);
+    }
+    (value & mask) as u16
+  }).collect()
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::biomes::BiomeRegistry;
+  use crate::blocks::StateRegistry;
+  use crate::region::Region;
+  use crate::world::{Chunk, WorldHeight};
+  use std::fs::File;
+
+  /**
+   * read the first generated chunk in a region file
+   */
+  fn first_chunk (path: &str) -> ChunkData {
+
+    let region = Region::from_reader(&mut File::open(path).unwrap()).unwrap();
+    let chunk = (0..32)
+      .flat_map(|x| (0..32).map(move |z| (x, z)))
+      .find_map(|(x, z)| region.get_chunk(x, z).unwrap())
+      .unwrap();
+    parse(&chunk.decompress().unwrap()).unwrap()
+
+  }
+
+  /**
+   * pack indices the way the game has since 1.16, filling the unused high bits of each long with
+   * ones so any that leak into an index show up
+   */
+  fn pack (indices: &[u16], bits: usize) -> Vec<i64> {
+
+    let per_long = 64 / bits;
+    indices.chunks(per_long).map(|chunk| {
+      let used = per_long * bits;
+      let mut long = if used == 64 { 0 } else { u64::MAX << used };
+      for (i, index) in chunk.iter().enumerate() {
+        long |= (*index as u64) << (i * bits);
+      }
+      long as i64
+    }).collect()
+
+  }
+
+  /**
+   * write out a chunk with one section of stone that's lit, in the layout of a version of the game
+   */
+  fn lit_chunk (version: i32, is_light_on: Option<bool>) -> Vec<u8> {
+
+    let compound = |fields: Vec<(&str, Value)>| {
+      Value::Compound(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    };
+    let light = |level: i8| Value::ByteArray(ByteArray::new(vec![level | level << 4; 2048]));
+    let stone = compound(vec![("Name", Value::String(String::from("minecraft:stone")))]);
+
+    let mut section = vec![("Y", Value::Byte(0)), ("BlockLight", light(7)), ("SkyLight", light(3))];
+    let mut chunk = vec![("DataVersion", Value::Int(version)), ("xPos", Value::Int(0)), ("zPos", Value::Int(0))];
+    if let Some(lit) = is_light_on {
+      chunk.push(("isLightOn", Value::Byte(lit as i8)));
+    }
+
+    let chunk = if version < NO_LEVEL_VERSION {
+      section.push(("Palette", Value::List(vec![stone])));
+      let sections = ("Sections", Value::List(vec![compound(section)]));
+      chunk.push(sections);
+      // everything but the version goes in the Level compound
+      let version = chunk.remove(0);
+      compound(vec![version, ("Level", compound(chunk))])
+    } else {
+      section.push(("block_states", compound(vec![("palette", Value::List(vec![stone]))])));
+      chunk.push(("sections", Value::List(vec![compound(section)])));
+      compound(chunk)
+    };
+    fastnbt::to_bytes(&chunk).unwrap()
+
+  }
+
+  #[test]
+  fn ignores_light_that_isnt_on () {
+
+    for version in [2586, 4189] {
+
+      let light = |is_light_on| {
+        let chunk = parse(&lit_chunk(version, is_light_on)).unwrap();
+        let light = &chunk.sections[0].light;
+        (light.block.as_ref().map(|block| block[0]), light.sky.as_ref().map(|sky| sky[0]))
+      };
+
+      assert_eq!(light(Some(true)), (Some(0x77), Some(0x33)), "version {}", version);
+      // chunks from before isLightOn was saved have finished light too
+      assert_eq!(light(None), (Some(0x77), Some(0x33)), "version {}", version);
+      assert_eq!(light(Some(false)), (None, None), "version {}", version);
+
+    }
+
+  }
+
+  #[test]
+  fn parses_every_fixture_chunk () {
+
+    for (path, count) in [("src/chunk_tests/superflat.mca", 529), ("src/chunk_tests/forest.mca", 457)] {
+      let region = Region::from_reader(&mut File::open(path).unwrap()).unwrap();
+      let mut states = StateRegistry::new();
+      let mut biomes = BiomeRegistry::new();
+      let mut parsed = 0;
+      for x in 0..32 {
+        for z in 0..32 {
+          let Some(chunk) = region.get_chunk(x, z).unwrap() else {
+            continue;
+          };
+          let data = parse(&chunk.decompress().unwrap()).unwrap();
+          assert_eq!((data.x.rem_euclid(32), data.z.rem_euclid(32)), (x as i32, z as i32));
+          let height = WorldHeight::overworld(data.data_version);
+          Chunk::from_data(data, height, &mut states, &mut biomes).unwrap();
+          parsed += 1;
+        }
+      }
+      assert_eq!(parsed, count, "{}", path);
+    }
+
+  }
+
+  #[test]
+  fn unpacks_superflat_layers () {
+
+    let chunk = first_chunk("src/chunk_tests/superflat.mca");
+    let section = chunk.sections.iter().find(|section| section.y == -4).unwrap();
+    let blocks = section.blocks.as_ref().unwrap();
+    let names: Vec<&str> = blocks.palette.iter().map(|state| state.name.as_str()).collect();
+    assert_eq!(names, ["minecraft:bedrock", "minecraft:dirt", "minecraft:grass_block", "minecraft:air"]);
+
+    // one layer of bedrock, two of dirt and one of grass, with air above
+    let indices = blocks.indices.as_ref().unwrap();
+    assert_eq!(indices.len(), 4096);
+    for (i, index) in indices.iter().enumerate() {
+      let expected = match i >> 8 {
+        0 => 0,
+        1 | 2 => 1,
+        3 => 2,
+        _ => 3
+      };
+      assert_eq!(*index, expected, "block {}", i);
+    }
+
+    // the sections above are all air, with no indices
+    let above = chunk.sections.iter().find(|section| section.y == -3).unwrap().blocks.as_ref().unwrap();
+    assert_eq!(above.palette.len(), 1);
+    assert!(above.indices.is_none());
+
+  }
+
+  #[test]
+  fn unpacks_forest_sections () {
+
+    let chunk = first_chunk("src/chunk_tests/forest.mca");
+
+    // every index is inside its palette, including in sections whose longs have padding
+    for section in &chunk.sections {
+      let blocks = section.blocks.as_ref().unwrap();
+      if let Some(indices) = &blocks.indices {
+        assert_eq!(indices.len(), 4096);
+        assert!(indices.iter().all(|index| (*index as usize) < blocks.palette.len()), "section {}", section.y);
+      }
+    }
+
+    // the bottom layer of the world is bedrock
+    let bottom = chunk.sections.iter().find(|section| section.y == -4).unwrap().blocks.as_ref().unwrap();
+    let indices = bottom.indices.as_ref().unwrap();
+    assert!(indices[..256].iter().all(|index| bottom.palette[*index as usize].name == "minecraft:bedrock"));
+
+  }
+
+  #[test]
+  fn small_palettes_use_four_bits () {
+
+    // 5 entries only need 3 bits, but indices always take at least 4
+    let indices: Vec<u16> = (0..4096).map(|i| (i * 7 % 5) as u16).collect();
+    let data = pack(&indices, 4);
+    assert_eq!(data.len(), 256);
+    assert_eq!(unpack_block_states(&data, 5), indices);
+
+  }
+
+  #[test]
+  fn skips_padding_bits () {
+
+    // 33 entries take 6 bits, so 10 fit in each long with 4 bits of padding left over
+    let indices: Vec<u16> = (0..4096).map(|i| (i * 13 % 33) as u16).collect();
+    let data = pack(&indices, 6);
+    assert_eq!(data.len(), 410);
+    assert_eq!(unpack_block_states(&data, 33), indices);
+
+  }
+
+  #[test]
+  fn unpacks_indices_spanning_two_longs () {
+
+    // 20 entries take 5 bits, so the 13th index is split between the last 4 bits of the first long
+    // and the first bit of the second
+    let data = [0x3 << 60, 1];
+    let indices = unpack_spanning_block_states(&data, 20);
+    assert_eq!(indices.len(), 4096);
+    assert_eq!(indices[12], 0b10011);
+    assert!(indices[..12].iter().all(|index| *index == 0));
+    assert!(indices[13..].iter().all(|index| *index == 0));
+
+    // and indices packed one after another all come back out
+    let indices: Vec<u16> = (0..4096).map(|i| (i * 7 % 20) as u16).collect();
+    let mut data = vec![0u64; 4096 * 5 / 64];
+    for (i, index) in indices.iter().enumerate() {
+      let bit = i * 5;
+      data[bit / 64] |= (*index as u64) << (bit % 64);
+      if bit % 64 > 59 {
+        data[bit / 64 + 1] |= (*index as u64) >> (64 - bit % 64);
+      }
+    }
+    let data: Vec<i64> = data.into_iter().map(|long| long as i64).collect();
+    assert_eq!(unpack_spanning_block_states(&data, 20), indices);
+
+  }
+
+  #[test]
+  fn reads_numbered_blocks () {
+
+    // the first block's id is 1 with 1 more in its extra bits, making 257 rather than stone, and
+    // the second is red wool, whose data is in the top half of the first byte
+    let mut blocks = vec![0i8; 4096];
+    blocks[0] = 1;
+    blocks[1] = 35;
+    blocks[2] = 1;
+    let mut add = vec![0i8; 2048];
+    add[0] = 0x01;
+    let mut data = vec![0i8; 2048];
+    data[0] = (14 << 4) as i8;
+
+    let section = LevelSectionNbt {
+      y: 0,
+      palette: None,
+      block_states: None,
+      blocks: Some(ByteArray::new(blocks)),
+      add: Some(ByteArray::new(add)),
+      data: Some(ByteArray::new(data)),
+      block_light: None,
+      sky_light: None
+    };
+    let blocks = numbered_section(&section);
+    let indices = blocks.indices.unwrap();
+    let name = |i: usize| blocks.palette[indices[i] as usize].name.as_str();
+
+    assert_eq!(name(0), "minecraft:unknown");
+    assert_eq!(name(1), "minecraft:red_wool");
+    assert_eq!(name(2), "minecraft:stone");
+    assert_eq!(name(3), "minecraft:air");
+    assert_ne!(indices[0], indices[2]);
+
+  }
+
+}
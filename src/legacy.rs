@@ -0,0 +1,436 @@
+/*
+ * names the numbered blocks of worlds from before 1.13, when each block was stored as an id and
+ * four bits of data instead of a name and properties. properties that depend on the blocks around
+ * them, like which way fences connect, weren't saved and are left for the block models to default
+ */
+use crate::blocks::BlockState;
+
+// the sixteen colours of wool, glass, terracotta and so on, in the order of their data values
+const COLOURS: [&str; 16] = [
+  "white", "orange", "magenta", "light_blue", "yellow", "lime", "pink", "gray",
+  "light_gray", "cyan", "purple", "blue", "brown", "green", "red", "black"
+];
+
+// the kinds of wood, in the order of their data values
+const WOODS: [&str; 6] = ["oak", "spruce", "birch", "jungle", "acacia", "dark_oak"];
+
+// the direction stairs face, from the lowest two bits of their data
+const STAIR_FACING: [&str; 4] = ["east", "west", "south", "north"];
+
+/**
+ * get the block state of a numbered block and its data
+ */
+pub fn legacy_state (id: u16, data: u8) -> BlockState {
+
+  let (name, properties) = legacy_block(id, data & 15);
+  BlockState::new(&name, properties.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+
+}
+
+/**
+ * get the name and properties of a numbered block
+ */
+fn legacy_block (id: u16, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  let colour = COLOURS[data as usize];
+  let wood = WOODS[(data & 7).min(5) as usize];
+  let plain = |name: &str| (name.to_string(), vec![]);
+
+  match id {
+    0 => plain("air"),
+    1 => plain(["stone", "granite", "polished_granite", "diorite", "polished_diorite", "andesite", "polished_andesite"][(data as usize).min(6)]),
+    2 => plain("grass_block"),
+    3 => plain(["dirt", "coarse_dirt", "podzol"][(data as usize).min(2)]),
+    4 => plain("cobblestone"),
+    5 => plain(&format!("{}_planks", wood)),
+    6 => plain(&format!("{}_sapling", wood)),
+    7 => plain("bedrock"),
+    // flowing and still liquids became the same block, with their level kept
+    8 | 9 => ("water".to_string(), vec![("level", data.to_string())]),
+    10 | 11 => ("lava".to_string(), vec![("level", data.to_string())]),
+    12 => plain(if data == 1 { "red_sand" } else { "sand" }),
+    13 => plain("gravel"),
+    14 => plain("gold_ore"),
+    15 => plain("iron_ore"),
+    16 => plain("coal_ore"),
+    17 => log(WOODS[(data & 3) as usize], data),
+    18 => plain(&format!("{}_leaves", WOODS[(data & 3) as usize])),
+    19 => plain(if data == 1 { "wet_sponge" } else { "sponge" }),
+    20 => plain("glass"),
+    21 => plain("lapis_ore"),
+    22 => plain("lapis_block"),
+    23 => facing("dispenser", data),
+    24 => plain(["sandstone", "chiseled_sandstone", "cut_sandstone"][(data as usize).min(2)]),
+    25 => plain("note_block"),
+    26 => plain("red_bed"),
+    27 => plain("powered_rail"),
+    28 => plain("detector_rail"),
+    29 => facing("sticky_piston", data),
+    30 => plain("cobweb"),
+    31 => plain(["dead_bush", "short_grass", "fern"][(data as usize).min(2)]),
+    32 => plain("dead_bush"),
+    33 => facing("piston", data),
+    34 => facing("piston_head", data),
+    35 => plain(&format!("{}_wool", colour)),
+    36 => plain("moving_piston"),
+    37 => plain("dandelion"),
+    38 => plain([
+      "poppy", "blue_orchid", "allium", "azure_bluet", "red_tulip", "orange_tulip", "white_tulip", "pink_tulip", "oxeye_daisy"
+    ][(data as usize).min(8)]),
+    39 => plain("brown_mushroom"),
+    40 => plain("red_mushroom"),
+    41 => plain("gold_block"),
+    42 => plain("iron_block"),
+    43 => slab(stone_slab(data), "double"),
+    44 => slab(stone_slab(data), if data & 8 != 0 { "top" } else { "bottom" }),
+    45 => plain("bricks"),
+    46 => plain("tnt"),
+    47 => plain("bookshelf"),
+    48 => plain("mossy_cobblestone"),
+    49 => plain("obsidian"),
+    50 => torch("torch", "wall_torch", data),
+    51 => plain("fire"),
+    52 => plain("spawner"),
+    53 => stairs("oak_stairs", data),
+    54 => plain("chest"),
+    55 => ("redstone_wire".to_string(), vec![("power", data.to_string())]),
+    56 => plain("diamond_ore"),
+    57 => plain("diamond_block"),
+    58 => plain("crafting_table"),
+    59 => ("wheat".to_string(), vec![("age", (data & 7).to_string())]),
+    60 => plain("farmland"),
+    61 => facing("furnace", data),
+    62 => {
+      let (name, mut properties) = facing("furnace", data);
+      properties.push(("lit", "true".to_string()));
+      (name, properties)
+    }
+    63 => ("oak_sign".to_string(), vec![("rotation", data.to_string())]),
+    64 => door("oak_door", data),
+    65 => facing("ladder", data),
+    66 => plain("rail"),
+    67 => stairs("cobblestone_stairs", data),
+    68 => facing("oak_wall_sign", data),
+    69 => plain("lever"),
+    70 => plain("stone_pressure_plate"),
+    71 => door("iron_door", data),
+    72 => plain("oak_pressure_plate"),
+    73 => plain("redstone_ore"),
+    74 => ("redstone_ore".to_string(), vec![("lit", "true".to_string())]),
+    75 => {
+      let (name, mut properties) = torch("redstone_torch", "redstone_wall_torch", data);
+      properties.push(("lit", "false".to_string()));
+      (name, properties)
+    }
+    76 => torch("redstone_torch", "redstone_wall_torch", data),
+    77 => plain("stone_button"),
+    78 => ("snow".to_string(), vec![("layers", ((data & 7) + 1).to_string())]),
+    79 => plain("ice"),
+    80 => plain("snow_block"),
+    81 => plain("cactus"),
+    82 => plain("clay"),
+    83 => plain("sugar_cane"),
+    84 => plain("jukebox"),
+    85 => plain("oak_fence"),
+    86 => horizontal("carved_pumpkin", data),
+    87 => plain("netherrack"),
+    88 => plain("soul_sand"),
+    89 => plain("glowstone"),
+    90 => plain("nether_portal"),
+    91 => horizontal("jack_o_lantern", data),
+    92 => plain("cake"),
+    93 | 94 => plain("repeater"),
+    95 => plain(&format!("{}_stained_glass", colour)),
+    96 => plain("oak_trapdoor"),
+    97 => plain([
+      "infested_stone", "infested_cobblestone", "infested_stone_bricks", "infested_mossy_stone_bricks",
+      "infested_cracked_stone_bricks", "infested_chiseled_stone_bricks"
+    ][(data as usize).min(5)]),
+    98 => plain(["stone_bricks", "mossy_stone_bricks", "cracked_stone_bricks", "chiseled_stone_bricks"][(data as usize).min(3)]),
+    99 => plain("brown_mushroom_block"),
+    100 => plain("red_mushroom_block"),
+    101 => plain("iron_bars"),
+    102 => plain("glass_pane"),
+    103 => plain("melon"),
+    104 => ("pumpkin_stem".to_string(), vec![("age", (data & 7).to_string())]),
+    105 => ("melon_stem".to_string(), vec![("age", (data & 7).to_string())]),
+    106 => plain("vine"),
+    107 => plain("oak_fence_gate"),
+    108 => stairs("brick_stairs", data),
+    109 => stairs("stone_brick_stairs", data),
+    110 => plain("mycelium"),
+    111 => plain("lily_pad"),
+    112 => plain("nether_bricks"),
+    113 => plain("nether_brick_fence"),
+    114 => stairs("nether_brick_stairs", data),
+    115 => ("nether_wart".to_string(), vec![("age", (data & 3).to_string())]),
+    116 => plain("enchanting_table"),
+    117 => plain("brewing_stand"),
+    118 => plain("cauldron"),
+    119 => plain("end_portal"),
+    120 => plain("end_portal_frame"),
+    121 => plain("end_stone"),
+    122 => plain("dragon_egg"),
+    123 => plain("redstone_lamp"),
+    124 => ("redstone_lamp".to_string(), vec![("lit", "true".to_string())]),
+    125 => slab(&format!("{}_slab", wood), "double"),
+    126 => slab(&format!("{}_slab", wood), if data & 8 != 0 { "top" } else { "bottom" }),
+    127 => plain("cocoa"),
+    128 => stairs("sandstone_stairs", data),
+    129 => plain("emerald_ore"),
+    130 => facing("ender_chest", data),
+    131 => plain("tripwire_hook"),
+    132 => plain("tripwire"),
+    133 => plain("emerald_block"),
+    134 => stairs("spruce_stairs", data),
+    135 => stairs("birch_stairs", data),
+    136 => stairs("jungle_stairs", data),
+    137 => plain("command_block"),
+    138 => plain("beacon"),
+    139 => plain(if data == 1 { "mossy_cobblestone_wall" } else { "cobblestone_wall" }),
+    140 => plain("flower_pot"),
+    141 => ("carrots".to_string(), vec![("age", (data & 7).to_string())]),
+    142 => ("potatoes".to_string(), vec![("age", (data & 7).to_string())]),
+    143 => plain("oak_button"),
+    144 => plain("skeleton_skull"),
+    145 => plain("anvil"),
+    146 => facing("trapped_chest", data),
+    147 => plain("light_weighted_pressure_plate"),
+    148 => plain("heavy_weighted_pressure_plate"),
+    149 | 150 => plain("comparator"),
+    151 => plain("daylight_detector"),
+    152 => plain("redstone_block"),
+    153 => plain("nether_quartz_ore"),
+    154 => plain("hopper"),
+    155 => match data {
+      1 => plain("chiseled_quartz_block"),
+      2 => ("quartz_pillar".to_string(), vec![("axis", "y".to_string())]),
+      3 => ("quartz_pillar".to_string(), vec![("axis", "x".to_string())]),
+      4 => ("quartz_pillar".to_string(), vec![("axis", "z".to_string())]),
+      _ => plain("quartz_block")
+    },
+    156 => stairs("quartz_stairs", data),
+    157 => plain("activator_rail"),
+    158 => facing("dropper", data),
+    159 => plain(&format!("{}_terracotta", colour)),
+    160 => plain(&format!("{}_stained_glass_pane", colour)),
+    161 => plain(if data & 3 == 1 { "dark_oak_leaves" } else { "acacia_leaves" }),
+    162 => log(if data & 3 == 1 { "dark_oak" } else { "acacia" }, data),
+    163 => stairs("acacia_stairs", data),
+    164 => stairs("dark_oak_stairs", data),
+    165 => plain("slime_block"),
+    166 => plain("barrier"),
+    167 => plain("iron_trapdoor"),
+    168 => plain(["prismarine", "prismarine_bricks", "dark_prismarine"][(data as usize).min(2)]),
+    169 => plain("sea_lantern"),
+    170 => pillar("hay_block", data),
+    171 => plain(&format!("{}_carpet", colour)),
+    172 => plain("terracotta"),
+    173 => plain("coal_block"),
+    174 => plain("packed_ice"),
+    // the top half of a tall plant doesn't say which plant it is, so it's taken to be tall grass
+    175 if data & 8 != 0 => ("tall_grass".to_string(), vec![("half", "upper".to_string())]),
+    175 => (
+      ["sunflower", "lilac", "tall_grass", "large_fern", "rose_bush", "peony"][(data as usize).min(5)].to_string(),
+      vec![("half", "lower".to_string())]
+    ),
+    176 => ("white_banner".to_string(), vec![("rotation", data.to_string())]),
+    177 => facing("white_wall_banner", data),
+    178 => ("daylight_detector".to_string(), vec![("inverted", "true".to_string())]),
+    179 => plain(["red_sandstone", "chiseled_red_sandstone", "cut_red_sandstone"][(data as usize).min(2)]),
+    180 => stairs("red_sandstone_stairs", data),
+    181 => slab("red_sandstone_slab", "double"),
+    182 => slab("red_sandstone_slab", if data & 8 != 0 { "top" } else { "bottom" }),
+    183 => plain("spruce_fence_gate"),
+    184 => plain("birch_fence_gate"),
+    185 => plain("jungle_fence_gate"),
+    186 => plain("dark_oak_fence_gate"),
+    187 => plain("acacia_fence_gate"),
+    188 => plain("spruce_fence"),
+    189 => plain("birch_fence"),
+    190 => plain("jungle_fence"),
+    191 => plain("dark_oak_fence"),
+    192 => plain("acacia_fence"),
+    193 => door("spruce_door", data),
+    194 => door("birch_door", data),
+    195 => door("jungle_door", data),
+    196 => door("acacia_door", data),
+    197 => door("dark_oak_door", data),
+    198 => facing("end_rod", data),
+    199 => plain("chorus_plant"),
+    200 => plain("chorus_flower"),
+    201 => plain("purpur_block"),
+    202 => pillar("purpur_pillar", data),
+    203 => stairs("purpur_stairs", data),
+    204 => slab("purpur_slab", "double"),
+    205 => slab("purpur_slab", if data & 8 != 0 { "top" } else { "bottom" }),
+    206 => plain("end_stone_bricks"),
+    207 => ("beetroots".to_string(), vec![("age", (data & 3).to_string())]),
+    208 => plain("dirt_path"),
+    209 => plain("end_gateway"),
+    210 => plain("repeating_command_block"),
+    211 => plain("chain_command_block"),
+    212 => plain("frosted_ice"),
+    213 => plain("magma_block"),
+    214 => plain("nether_wart_block"),
+    215 => plain("red_nether_bricks"),
+    216 => pillar("bone_block", data),
+    217 => plain("structure_void"),
+    218 => facing("observer", data),
+    219..=234 => facing(&format!("{}_shulker_box", COLOURS[id as usize - 219]), data),
+    235..=250 => horizontal(&format!("{}_glazed_terracotta", COLOURS[id as usize - 235]), data),
+    251 => plain(&format!("{}_concrete", colour)),
+    252 => plain(&format!("{}_concrete_powder", colour)),
+    255 => plain("structure_block"),
+    _ => plain("unknown")
+  }
+
+}
+
+/**
+ * get the name of a stone slab from its data, which also says whether it's the top half
+ */
+fn stone_slab (data: u8) -> &'static str {
+  [
+    "smooth_stone_slab", "sandstone_slab", "petrified_oak_slab", "cobblestone_slab",
+    "brick_slab", "stone_brick_slab", "nether_brick_slab", "quartz_slab"
+  ][(data & 7) as usize]
+}
+
+/**
+ * a slab, which is either the top or bottom half of a block, or both
+ */
+fn slab (name: &str, kind: &str) -> (String, Vec<(&'static str, String)>) {
+  (name.to_string(), vec![("type", kind.to_string())])
+}
+
+/**
+ * a log, whose data says the kind of wood in its lowest two bits and which way it lies in the next
+ * two. logs with bark on every side became their own wood block
+ */
+fn log (wood: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  let axis = match (data >> 2) & 3 {
+    0 => "y",
+    1 => "x",
+    2 => "z",
+    _ => return (format!("{}_wood", wood), vec![("axis", "y".to_string())])
+  };
+  (format!("{}_log", wood), vec![("axis", axis.to_string())])
+
+}
+
+/**
+ * a block that lies along an axis, saved in the same bits as a log's
+ */
+fn pillar (name: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  let axis = match (data >> 2) & 3 {
+    1 => "x",
+    2 => "z",
+    _ => "y"
+  };
+  (name.to_string(), vec![("axis", axis.to_string())])
+
+}
+
+/**
+ * stairs, facing the direction in their lowest two bits and upside down if the next is set
+ */
+fn stairs (name: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  let half = if data & 4 != 0 { "top" } else { "bottom" };
+  (name.to_string(), vec![
+    ("facing", STAIR_FACING[(data & 3) as usize].to_string()),
+    ("half", half.to_string())
+  ])
+
+}
+
+/**
+ * a block that can face any direction, numbered down, up, north, south, west and east
+ */
+fn facing (name: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  let facing = ["down", "up", "north", "south", "west", "east"].get((data & 7) as usize).unwrap_or(&"north");
+  (name.to_string(), vec![("facing", facing.to_string())])
+
+}
+
+/**
+ * a block that faces one of the four sides, numbered south, west, north and east
+ */
+fn horizontal (name: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+  (name.to_string(), vec![("facing", ["south", "west", "north", "east"][(data & 3) as usize].to_string())])
+}
+
+/**
+ * a torch, which is stood on the ground or against a wall facing east, west, south or north
+ */
+fn torch (name: &str, wall_name: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  match data {
+    1..=4 => (wall_name.to_string(), vec![("facing", ["east", "west", "south", "north"][data as usize - 1].to_string())]),
+    _ => (name.to_string(), vec![])
+  }
+
+}
+
+/**
+ * a door, whose data only says which half of the door it is that the game can't work out itself
+ */
+fn door (name: &str, data: u8) -> (String, Vec<(&'static str, String)>) {
+
+  let half = if data & 8 != 0 { "upper" } else { "lower" };
+  (name.to_string(), vec![("half", half.to_string())])
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  /**
+   * get the name and properties of a numbered block, as strings
+   */
+  fn state (id: u16, data: u8) -> (String, Vec<(String, String)>) {
+    let state = legacy_state(id, data);
+    (state.name, state.properties.into_iter().collect())
+  }
+
+  /**
+   * build the properties a block is expected to have
+   */
+  fn properties (properties: &[(&str, &str)]) -> Vec<(String, String)> {
+    properties.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+  }
+
+  #[test]
+  fn names_blocks_by_their_data () {
+
+    assert_eq!(state(1, 0).0, "minecraft:stone");
+    assert_eq!(state(1, 5).0, "minecraft:andesite");
+    assert_eq!(state(35, 14).0, "minecraft:red_wool");
+    assert_eq!(state(5, 2).0, "minecraft:birch_planks");
+
+    // only the lowest four bits are data
+    assert_eq!(state(35, 14 | 16).0, "minecraft:red_wool");
+
+    // ids nothing was ever numbered as are unknown
+    assert_eq!(state(257, 0).0, "minecraft:unknown");
+
+  }
+
+  #[test]
+  fn reads_properties_from_data () {
+
+    assert_eq!(state(17, 4), (String::from("minecraft:oak_log"), properties(&[("axis", "x")])));
+    assert_eq!(state(17, 2 | 8), (String::from("minecraft:birch_log"), properties(&[("axis", "z")])));
+    assert_eq!(state(17, 12), (String::from("minecraft:oak_wood"), properties(&[("axis", "y")])));
+    assert_eq!(state(53, 2 | 4), (String::from("minecraft:oak_stairs"), properties(&[("facing", "south"), ("half", "top")])));
+    assert_eq!(state(9, 0), (String::from("minecraft:water"), properties(&[("level", "0")])));
+
+  }
+
+}
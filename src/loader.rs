@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+#[cfg(target_arch = "wasm32")]
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+
+/*
+ * decompresses, parses and meshes chunks away from the event loop, so the window keeps drawing
+ * while the world streams in. on the web, where there aren't any threads, the same jobs are worked
+ * through a few at a time each frame instead
+ */
+use crate::chunk_format::{self, ChunkData};
+use crate::mesh::{Mesh, MeshMode};
+use crate::models::BlockModels;
+use crate::region::RegionChunk;
+use crate::save::RegionStore;
+use crate::world::{ChunkArea, WorldError};
+
+// how many jobs are done each frame on the web
+#[cfg(target_arch = "wasm32")]
+const JOBS_PER_FRAME: usize = 4;
+
+/**
+ * the work of loading a single chunk
+ */
+pub enum LoadJob {
+  // read a chunk from a save's region files, then decompress and parse it
+  Read { pos: (i32, i32), regions: Arc<Mutex<RegionStore>> },
+  // decompress and parse a chunk that's already been read from a region file
+  Parse { data: RegionChunk }
+}
+
+/**
+ * a job that's been finished
+ */
+pub enum Finished {
+  // pos is where the chunk was asked to be read from, if it was, and chunk is None if it hasn't
+  // been generated
+  Loaded { pos: Option<(i32, i32)>, chunk: Result<Option<ChunkData>, WorldError> },
+  Meshed { pos: (i32, i32), mesh: Mesh }
+}
+
+enum Job {
+  Load(LoadJob),
+  Mesh { pos: (i32, i32), area: ChunkArea, models: Arc<BlockModels>, mode: MeshMode, id: u64 }
+}
+
+enum Done {
+  Loaded { pos: Option<(i32, i32)>, chunk: Result<Option<ChunkData>, WorldError> },
+  Meshed { pos: (i32, i32), mesh: Mesh, id: u64 }
+}
+
+pub struct ChunkLoader {
+  #[cfg(not(target_arch = "wasm32"))]
+  jobs: mpsc::Sender<Job>,
+  #[cfg(not(target_arch = "wasm32"))]
+  done: mpsc::Receiver<Done>,
+  #[cfg(target_arch = "wasm32")]
+  queue: VecDeque<Job>,
+  latest_meshes: HashMap<(i32, i32), u64>, // the newest mesh job of each chunk, so older ones can be thrown away
+  next_id: u64
+}
+
+impl ChunkLoader {
+
+  /**
+   * start a pool of worker threads, leaving one core free for the event loop
+   */
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn new () -> ChunkLoader {
+
+    let (jobs, job_receiver) = mpsc::channel::<Job>();
+    let (done_sender, done) = mpsc::channel();
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+    let threads = std::thread::available_parallelism().map(|count| count.get()).unwrap_or(2);
+    for index in 0..threads.saturating_sub(1).max(1) {
+
+      let job_receiver = job_receiver.clone();
+      let done_sender = done_sender.clone();
+      std::thread::Builder::new()
+        .name(format!("chunk worker {}", index))
+        .spawn(move || loop {
+          // only hold the lock while waiting, so the other workers can take jobs while this one works
+          let job = job_receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+          // stop once the loader is dropped, like when a different world is opened
+          let Ok(job) = job else {
+            break;
+          };
+          if done_sender.send(run(job)).is_err() {
+            break;
+          }
+        })
+        .expect("couldn't start a chunk worker thread");
+
+    }
+
+    ChunkLoader {
+      jobs,
+      done,
+      latest_meshes: HashMap::new(),
+      next_id: 0
+    }
+
+  }
+
+  /**
+   * create a loader that does its jobs on the main thread, since there are no threads on the web
+   */
+  #[cfg(target_arch = "wasm32")]
+  pub fn new () -> ChunkLoader {
+    ChunkLoader {
+      queue: VecDeque::new(),
+      latest_meshes: HashMap::new(),
+      next_id: 0
+    }
+  }
+
+  /**
+   * start loading a chunk
+   */
+  pub fn load (&mut self, job: LoadJob) {
+    self.send(Job::Load(job));
+  }
+
+  /**
+   * start meshing the middle chunk of an area. if the chunk is meshed again before this finishes,
+   * only the newer mesh is handed back
+   */
+  pub fn mesh (&mut self, pos: (i32, i32), area: ChunkArea, models: Arc<BlockModels>, mode: MeshMode) {
+
+    let id = self.next_id;
+    self.next_id += 1;
+    self.latest_meshes.insert(pos, id);
+    self.send(Job::Mesh { pos, area, models, mode, id });
+
+  }
+
+  /**
+   * collect the jobs that have finished since the last call
+   */
+  pub fn poll (&mut self) -> Vec<Finished> {
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let done: Vec<Done> = self.done.try_iter().collect();
+    #[cfg(target_arch = "wasm32")]
+    let done: Vec<Done> = {
+      let count = self.queue.len().min(JOBS_PER_FRAME);
+      self.queue.drain(..count).map(run).collect()
+    };
+
+    done.into_iter().filter_map(|done| match done {
+      Done::Loaded { pos, chunk } => Some(Finished::Loaded { pos, chunk }),
+      Done::Meshed { pos, mesh, id } => {
+        // meshes can finish out of order, and an older one would be missing newer neighbours
+        if self.latest_meshes.get(&pos) != Some(&id) {
+          return None;
+        }
+        self.latest_meshes.remove(&pos);
+        Some(Finished::Meshed { pos, mesh })
+      }
+    }).collect()
+
+  }
+
+  /**
+   * hand a job to the workers, or queue it up for later on the web
+   */
+  fn send (&mut self, job: Job) {
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      // the workers only stop once the loader is dropped, so this can't fail
+      let _ = self.jobs.send(job);
+    }
+    #[cfg(target_arch = "wasm32")]
+    self.queue.push_back(job);
+
+  }
+
+}
+
+/**
+ * do a job
+ */
+fn run (job: Job) -> Done {
+
+  match job {
+    Job::Load(LoadJob::Read { pos, regions }) => {
+      // the lock is only held while reading, so other workers can parse chunks at the same time
+      let data = regions.lock().unwrap_or_else(PoisonError::into_inner).read_chunk(pos.0, pos.1);
+      Done::Loaded {
+        pos: Some(pos),
+        chunk: data.and_then(|data| data.map(|data| parse_chunk(&data)).transpose())
+      }
+    }
+    Job::Load(LoadJob::Parse { data }) => Done::Loaded {
+      pos: None,
+      chunk: parse_chunk(&data).map(Some)
+    },
+    Job::Mesh { pos, area, models, mode, id } => Done::Meshed {
+      pos,
+      mesh: Mesh::from_chunk(&area, &models, mode),
+      id
+    }
+  }
+
+}
+
+/**
+ * decompress and parse the nbt of a chunk
+ */
+fn parse_chunk (data: &RegionChunk) -> Result<ChunkData, WorldError> {
+  chunk_format::parse(&data.decompress()?)
+}
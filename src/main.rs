@@ -1,29 +1,88 @@
+mod biomes;
+mod blocks;
 mod render;
 mod camera;
+mod chunk_format;
+mod legacy;
+mod loader;
+mod mesh;
+mod models;
+mod options;
+mod region;
+mod resources;
+mod save;
 mod texture;
 mod world;
 use winit::{
-  event::{Event, WindowEvent},
+  event::{DeviceEvent, ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
   event_loop::EventLoop,
-  window::Window,
+  keyboard::{KeyCode, PhysicalKey},
+  window::{CursorGrabMode, Window},
 };
+use std::collections::HashSet;
 use std::fs::File;
+use std::sync::Arc;
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
+use options::Options;
 
-  // create the world
-  let mut file = File::open("src/chunk_tests/superflat.mca").unwrap();
-  let world = world::World::parse_world(&mut file);
+// the most chunks being read from disk at once, so the nearest ones are always loaded first
+const MAX_CHUNKS_LOADING: usize = 16;
 
-  // let world = world::World::test();
-  // generate the instances
-  let instances = world.gen_instances();
+async fn run(event_loop: EventLoop<()>, window: Window, options: Options, world: world::World, focus: Option<[f32; 3]>, atlas: texture::Atlas, pack: Option<resources::ResourcePack>) {
+
+  let mut world = world;
+
+  // merge faces together when meshing if asked to
+  let mode = if options.greedy {
+    mesh::MeshMode::Greedy
+  } else {
+    mesh::MeshMode::Culled
+  };
+
+  // the models are shared with the chunks being meshed in the background
+  let mut models = Arc::new(models::BlockModels::new(pack));
+  // chunks are loaded and meshed off the main thread, and each gets its own mesh once it's ready
+  let mut loader = loader::ChunkLoader::new();
+  // chunks still drawn from before the world was reloaded, until they're loaded again
+  let mut stale: HashSet<(i32, i32)> = HashSet::new();
   
   let mut camera = camera::Camera::new();
-  let mut render = render::Render::new(&window, &mut camera, instances).await;
+  let mut render = render::Render::new(&window, &mut camera, &atlas).await;
+
+  // draw out to the render distance, as far as the corners of the furthest chunks
+  let (znear, _) = camera.clip_planes();
+  camera.set_clip_planes(znear, (options.render_distance + 1) as f32 * 16.0 * std::f32::consts::SQRT_2);
+
+  // start off where asked to. otherwise look at the starting point straight away, so the chunks
+  // around it are loaded first, and frame the chunk there once the world has loaded
+  let mut framed = options.position.is_some();
+  match (options.position, focus) {
+    (Some(position), _) => camera.fly_to(position),
+    (None, Some(focus)) => camera.frame_box([focus[0] - 8.0, focus[1] - 8.0, focus[2] - 8.0], [focus[0] + 8.0, focus[1] + 8.0, focus[2] + 8.0]),
+    (None, None) => {}
+  }
+  if let Some([yaw, pitch]) = options.rotation {
+    camera.set_game_rotation(yaw, pitch);
+  }
+
+  // whether the mouse is locked to the window for looking around
+  let mut cursor_grabbed = false;
+  // the mouse button being held to drag the orbit camera around
+  let mut dragging: Option<MouseButton> = None;
 
   event_loop.run(move |event, target| {
     // Have the closure take ownership of the resources.
+
+    // mouse look uses raw motion, so it keeps working once the cursor hits the edge of the window
+    if let Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } = event {
+      match dragging {
+        _ if cursor_grabbed => camera.process_mouse(delta.0, delta.1),
+        Some(MouseButton::Left) => camera.orbit(delta.0, delta.1),
+        Some(_) => camera.pan(delta.0, delta.1),
+        None => {}
+      }
+      return;
+    }
     
     if let Event::WindowEvent {
       window_id: _,
@@ -37,11 +96,162 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
           
         }
         WindowEvent::RedrawRequested => {
+          // keep the camera from wandering off far above or below the world
+          if let Some((min_y, max_y)) = world.y_range() {
+            camera.set_height_limits(min_y as f32, max_y as f32);
+          }
           camera.update();
+
+          // pick up whatever the workers have finished since the last frame
+          let mut changed = vec![];
+          for finished in loader.poll() {
+            match finished {
+              loader::Finished::Loaded { pos, chunk } => changed.extend(world.add_chunk(pos, chunk)),
+              // meshes of chunks that have been unloaded since are thrown away
+              loader::Finished::Meshed { pos, mesh } => {
+                if world.loaded_chunks.contains_key(&pos) {
+                  render.upload_chunk(pos, &mesh);
+                  stale.remove(&pos);
+                }
+              }
+            }
+          }
+
+          // keep the chunks around the camera loaded
+          let centre = camera.focus();
+          let chunk = ((centre[0] / 16.0).floor() as i32, (centre[2] / 16.0).floor() as i32);
+          let update = world.stream(chunk.0, chunk.1, options.render_distance as i32, MAX_CHUNKS_LOADING);
+          for job in update.jobs {
+            loader.load(job);
+          }
+          for pos in update.unloaded {
+            render.remove_chunk(pos);
+            changed.push(pos);
+          }
+
+          // and remesh the chunks that changed, along with their neighbours
+          if !changed.is_empty() {
+            if !models.is_up_to_date(&world.states, &world.biomes) {
+              Arc::make_mut(&mut models).update(&world.states, &world.biomes, &atlas);
+            }
+            for pos in world.changed_chunks(&changed) {
+              if let Some(area) = world.chunk_area(pos) {
+                loader.mesh(pos, area, models.clone(), mode);
+              }
+            }
+          }
+
+          // once the reloaded world has caught up, stop drawing the chunks it no longer has. the ones
+          // it does have keep their old meshes until the new ones arrive
+          if !stale.is_empty() && !world.is_loading() {
+            for pos in stale.drain() {
+              if !world.loaded_chunks.contains_key(&pos) {
+                render.remove_chunk(pos);
+              }
+            }
+          }
+
+          // look at the starting chunk once everything around it has loaded
+          if !framed && !world.is_loading() {
+            let chunk = match focus {
+              Some(focus) => world.nearest_chunk(focus[0], focus[2]),
+              None => world.centre_chunk()
+            };
+            if let Some(chunk) = chunk {
+              frame_chunk(&mut camera, chunk);
+            }
+            framed = true;
+          }
+
           render.update_camera(&camera);
           render.render();
           render.window.request_redraw();
         }
+        WindowEvent::KeyboardInput {
+          event: KeyEvent { physical_key: PhysicalKey::Code(KeyCode::F5), state: ElementState::Pressed, .. },
+          ..
+        } => {
+          // reload the world from disk, in case it's been changed since
+          world = match load_world(&options) {
+            Ok((world, _)) => world,
+            Err(err) => {
+              println!("couldn't reload the world: {}", err);
+              return;
+            }
+          };
+          // the new world numbers its block states from scratch, and anything still loading is
+          // from the old one. what's on screen stays there until it's been remeshed
+          Arc::make_mut(&mut models).clear();
+          loader = loader::ChunkLoader::new();
+          stale.extend(render.chunk_positions());
+        }
+        WindowEvent::KeyboardInput {
+          event: KeyEvent { physical_key: PhysicalKey::Code(key), state, repeat, .. },
+          ..
+        } => {
+          let pressed = state == ElementState::Pressed;
+          match key {
+            // tab swaps between orbiting and flying
+            KeyCode::Tab if pressed && !repeat => {
+              camera.toggle_mode();
+              if camera.mode == camera::CameraMode::Orbit {
+                cursor_grabbed = set_cursor_grab(render.window, false);
+              }
+            }
+            KeyCode::Escape if pressed => cursor_grabbed = set_cursor_grab(render.window, false),
+            // p cycles through the projections, and t and i switch to top down and isometric maps
+            KeyCode::KeyP if pressed && !repeat => camera.cycle_projection(),
+            KeyCode::KeyT if pressed && !repeat => camera.set_map_view(0.0, -std::f32::consts::FRAC_PI_2),
+            KeyCode::KeyI if pressed && !repeat => camera.set_map_view(std::f32::consts::FRAC_PI_4, -(0.5f32.sqrt()).atan()),
+            // - and = narrow and widen the field of view
+            KeyCode::Minus | KeyCode::Equal if pressed => {
+              let step = if key == KeyCode::Minus { -5.0 } else { 5.0 };
+              camera.set_fovy(camera.fovy() + step);
+            }
+            // page up and page down push the far plane out and pull it back in
+            KeyCode::PageUp | KeyCode::PageDown if pressed => {
+              let (znear, zfar) = camera.clip_planes();
+              let scale = if key == KeyCode::PageUp { 2.0 } else { 0.5 };
+              camera.set_clip_planes(znear, zfar * scale);
+            }
+            // f frames whichever chunk the camera is closest to
+            KeyCode::KeyF if pressed && !repeat => {
+              let focus = camera.focus();
+              if let Some(chunk) = world.nearest_chunk(focus[0], focus[2]) {
+                frame_chunk(&mut camera, chunk);
+                cursor_grabbed = set_cursor_grab(render.window, false);
+              }
+            }
+            _ => {
+              camera.process_key(key, pressed);
+            }
+          }
+        }
+        // clicking into the window while flying locks the mouse for looking around
+        WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }
+          if camera.mode == camera::CameraMode::Fly && !cursor_grabbed => {
+          cursor_grabbed = set_cursor_grab(render.window, true);
+        }
+        // dragging with the left button turns the orbit camera, and with the others pans it
+        WindowEvent::MouseInput { state, button, .. } => {
+          dragging = match state {
+            ElementState::Pressed if camera.mode == camera::CameraMode::Orbit => Some(button),
+            _ => None
+          };
+        }
+        WindowEvent::MouseWheel { delta, .. } => {
+          // trackpads scroll in pixels rather than lines
+          let steps = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0
+          };
+          camera.zoom(steps);
+        }
+        WindowEvent::Focused(false) => {
+          camera.release_keys();
+          dragging = None;
+          cursor_grabbed = set_cursor_grab(render.window, false);
+        }
         WindowEvent::CloseRequested => target.exit(),
         _ => {}
       };
@@ -50,13 +260,148 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
   .unwrap();
 }
 
+/**
+ * open the world from the path given on the command line, returning it along with the block
+ * position to start off looking at. for saves that's the spawn point unless another was asked for,
+ * and for single region files it's None, to look at the middle of the region instead
+ */
+fn load_world (options: &Options) -> Result<(world::World, Option<[f32; 3]>), String> {
+
+  let path = &options.path;
+  if !path.exists() {
+    return Err(format!("{} doesn't exist", path.display()));
+  }
+
+  // a single region file is loaded all at once, and there's no telling which dimension it's from
+  if !path.is_dir() {
+    if options.dimension.is_some() {
+      return Err(format!("{} is a single region file, --dimension only works for world saves", path.display()));
+    }
+    let mut file = File::open(path).map_err(|err| format!("couldn't open {}: {}", path.display(), err))?;
+    let world = world::World::parse_world(&mut file).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?;
+    return Ok((world, options.position));
+  }
+
+  let save = save::Save::open(path).map_err(|err| format!("{} isn't a world save, couldn't read its level.dat: {}", path.display(), err))?;
+  let dimension = options.dimension.unwrap_or(save::Dimension::Overworld);
+  if !save.dimensions.contains(&dimension) {
+    return Err(format!("{} has no {:?} dimension, only {:?}", path.display(), dimension, save.dimensions));
+  }
+
+  let level = &save.level;
+  println!(
+    "opened {} ({}, data version {}), spawn at {} {} {}",
+    level.name, level.version.as_deref().unwrap_or("unknown version"), level.data_version.unwrap_or(0),
+    level.spawn[0], level.spawn[1], level.spawn[2]
+  );
+
+  // the spawn point is only in the overworld
+  let focus = match (options.position, dimension) {
+    (Some(position), _) => position,
+    (None, save::Dimension::Overworld) => level.spawn.map(|value| value as f32),
+    (None, _) => [0.0; 3]
+  };
+
+  // a broken dimension type isn't worth giving up over, the world is probably the usual height
+  let height = save.height(dimension).unwrap_or_else(|err| {
+    println!("using the default height for the {:?}: {}", dimension, err);
+    dimension.default_height(level.data_version)
+  });
+
+  // the chunks are loaded around the camera once it's there
+  Ok((world::World::from_regions(save.regions(dimension), height), Some(focus)))
+
+}
+
+/**
+ * load the block textures, from a resource pack if one was given or otherwise the ones that come
+ * with the viewer, and pack them together into an atlas
+ */
+fn load_textures (options: &Options) -> Result<(texture::Atlas, Option<resources::ResourcePack>), String> {
+
+  match &options.resource_pack {
+    Some(path) => {
+      let mut pack = resources::ResourcePack::open(path).map_err(|err| format!("couldn't open resource pack {}: {}", path.display(), err))?;
+      Ok((texture::Atlas::new(std::mem::take(&mut pack.textures)), Some(pack)))
+    }
+    None => Ok((texture::Atlas::built_in(), None))
+  }
+
+}
+
+/**
+ * read the command line options, printing how to use the viewer and quitting if they're wrong or
+ * help was asked for
+ */
+fn parse_options () -> Options {
+
+  match Options::parse(std::env::args().skip(1)) {
+    Ok(options) => options,
+    Err(err) if err.is_empty() => {
+      println!("{}", options::USAGE);
+      std::process::exit(0);
+    }
+    Err(err) => {
+      eprintln!("error: {}\n\n{}", err, options::USAGE);
+      std::process::exit(1);
+    }
+  }
+
+}
+
+/**
+ * print an error and quit
+ */
+fn exit_with_error (err: String) -> ! {
+  eprintln!("error: {}", err);
+  std::process::exit(1);
+}
+
+/**
+ * point the orbit camera at a chunk, so everything that isn't air in it is in view
+ */
+fn frame_chunk (camera: &mut camera::Camera, chunk: &world::Chunk) {
+
+  if let Some((min, max)) = chunk.bounds() {
+    camera.frame_box(min, max);
+  }
+
+}
+
+/**
+ * lock or unlock the mouse to the window and hide it, returning whether it ended up locked
+ */
+fn set_cursor_grab (window: &Window, grab: bool) -> bool {
+
+  if !grab {
+    let _ = window.set_cursor_grab(CursorGrabMode::None);
+    window.set_cursor_visible(true);
+    return false;
+  }
+
+  // some platforms can only confine the cursor rather than lock it in place
+  let grabbed = window.set_cursor_grab(CursorGrabMode::Locked)
+    .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+    .is_ok();
+  window.set_cursor_visible(!grabbed);
+  grabbed
+
+}
+
 pub fn main() {
+  // load everything before opening the window, so mistakes on the command line are reported
+  // straight away
+  let options = parse_options();
+  let (world, focus) = load_world(&options).unwrap_or_else(|err| exit_with_error(err));
+  let (atlas, pack) = load_textures(&options).unwrap_or_else(|err| exit_with_error(err));
+
   let event_loop = EventLoop::new().unwrap();
   #[cfg_attr(
     not(target_arch = "wasm32"),
     expect(unused_mut, reason = "`wasm32` re-assigns to specify canvas")
   )]
-  let mut builder = winit::window::WindowBuilder::new();
+  let mut builder = winit::window::WindowBuilder::new()
+    .with_inner_size(winit::dpi::PhysicalSize::new(options.window_size[0], options.window_size[1]));
   #[cfg(target_arch = "wasm32")]
   {
     use wasm_bindgen::JsCast;
@@ -76,12 +421,12 @@ pub fn main() {
   #[cfg(not(target_arch = "wasm32"))]
   {
     env_logger::init();
-    pollster::block_on(run(event_loop, window));
+    pollster::block_on(run(event_loop, window, options, world, focus, atlas, pack));
   }
   #[cfg(target_arch = "wasm32")]
   {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
     console_log::init().expect("could not initialize logger");
-    wasm_bindgen_futures::spawn_local(run(event_loop, window));
+    wasm_bindgen_futures::spawn_local(run(event_loop, window, options, world, focus, atlas, pack));
   }
 }
@@ -0,0 +1,620 @@
+/*
+ * turns chunks into meshes of the block faces that can actually be seen, tinted by the biomes
+ * around them and lit by the light saved with the chunk
+ */
+use crate::biomes::Tint;
+use crate::blocks::{self, AIR_STATE};
+use crate::models::{BlockModels, ModelQuad};
+use crate::render::Vertex;
+use crate::world::{Chunk, ChunkArea, Light};
+
+pub struct Face {
+  pub normal: [i32; 3],
+  pub corners: [[f32; 3]; 4]
+}
+
+// the six faces of a unit cube, with corners listed bottom left, bottom right, top right, top left
+// as seen from outside the block
+pub const FACES: [Face; 6] = [
+  // east
+  Face { normal: [ 1,  0,  0], corners: [[1.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [1.0, 1.0, 1.0]] },
+  // west
+  Face { normal: [-1,  0,  0], corners: [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 1.0], [0.0, 1.0, 0.0]] },
+  // top
+  Face { normal: [ 0,  1,  0], corners: [[0.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]] },
+  // bottom
+  Face { normal: [ 0, -1,  0], corners: [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 1.0], [0.0, 0.0, 1.0]] },
+  // south
+  Face { normal: [ 0,  0,  1], corners: [[0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]] },
+  // north
+  Face { normal: [ 0,  0, -1], corners: [[1.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]] }
+];
+
+// the direction each face points in, as block models name them
+pub const FACE_NAMES: [&str; 6] = ["east", "west", "up", "down", "south", "north"];
+
+// texture coordinates of each corner of a face, in the same order as the corners
+const FACE_TEX_COORDS: [[f32; 2]; 4] = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+
+// how many blocks either side of a corner the biome colours are blended over, like the game's
+// default biome blend
+const BLEND_RADIUS: i32 = 2;
+
+// the colour of faces that aren't tinted
+const WHITE: u32 = 0xffffff;
+
+/**
+ * how to build the mesh of a chunk
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshMode {
+  // one quad for every visible block face
+  Culled,
+  // visible faces with the same texture merged into larger quads
+  Greedy
+}
+
+pub struct Mesh {
+  pub vertices: Vec<Vertex>,
+  pub indices: Vec<u32>
+}
+
+impl Mesh {
+
+  /**
+   * create an empty mesh
+   */
+  pub fn new () -> Mesh {
+    Mesh {
+      vertices: vec![],
+      indices: vec![]
+    }
+  }
+
+  /**
+   * create a mesh of the visible faces in the middle chunk of an area. faces on the edge of the
+   * chunk are checked against the neighbouring chunks around it
+   */
+  pub fn from_chunk (area: &ChunkArea, models: &BlockModels, mode: MeshMode) -> Mesh {
+
+    let chunk = &area.chunk;
+    match mode {
+      MeshMode::Culled => Mesh::culled(area, models, chunk),
+      MeshMode::Greedy => Mesh::greedy(area, models, chunk)
+    }
+
+  }
+
+  /**
+   * create a mesh with a quad for each visible face in a chunk
+   */
+  fn culled (area: &ChunkArea, models: &BlockModels, chunk: &Chunk) -> Mesh {
+
+    let mut mesh = Mesh::new();
+
+    for (index, section) in chunk.sections.iter().enumerate() {
+
+      let Some(section) = section else {
+        continue;
+      };
+      let y_offset = chunk.height.min_y + index as i32 * 16;
+
+      for y in 0..16 {
+
+        for z in 0..16 {
+
+          for x in 0..16 {
+
+            let state = section.get_block(x, y, z);
+            if state == AIR_STATE {
+              continue;
+            }
+
+            let pos = [x as i32, y_offset + y as i32, z as i32];
+
+            if !models.is_full_cube(state) {
+              mesh.push_model(area, models, chunk, pos, state);
+              continue;
+            }
+
+            for (face_index, face) in FACES.iter().enumerate() {
+
+              let neighbour_pos = [
+                pos[0] + face.normal[0],
+                pos[1] + face.normal[1],
+                pos[2] + face.normal[2]
+              ];
+
+              if is_face_hidden(models, state, area.get_block(neighbour_pos)) {
+                continue;
+              }
+
+              // faces are lit by the light in the block they face
+              let tile = models.face_tile(state, face_index);
+              let tints = face_tints(area, models, state, face_index, pos);
+              let light = area.get_light(neighbour_pos);
+              mesh.push_quad([chunk.x * 16 + pos[0], pos[1], chunk.z * 16 + pos[2]], [1, 1, 1], face, tile, tints, light);
+
+            }
+
+          }
+
+        }
+
+      }
+
+    }
+
+    mesh
+
+  }
+
+  /**
+   * create a mesh where neighbouring faces with the same texture are merged together. faces are
+   * only merged within a section, so each quad is at most 16x16
+   */
+  fn greedy (area: &ChunkArea, models: &BlockModels, chunk: &Chunk) -> Mesh {
+
+    let mut mesh = Mesh::new();
+
+    for (index, section) in chunk.sections.iter().enumerate() {
+
+      let Some(section) = section else {
+        continue;
+      };
+      let y_offset = chunk.height.min_y + index as i32 * 16;
+
+      for (face_index, face) in FACES.iter().enumerate() {
+
+        // the axis the face points along, and the two axes across it
+        let axis = face.normal.iter().position(|n| *n != 0).unwrap();
+        let u_axis = (axis + 1) % 3;
+        let v_axis = (axis + 2) % 3;
+
+        for layer in 0..16 {
+
+          // the tile, tint and light of each visible face in this layer of the section, [v][u]
+          let mut mask: [[Option<(u32, u32, Light)>; 16]; 16] = [[None; 16]; 16];
+
+          for (v, row) in mask.iter_mut().enumerate() {
+
+            for (u, cell) in row.iter_mut().enumerate() {
+
+              let mut local = [0; 3];
+              local[axis] = layer;
+              local[u_axis] = u;
+              local[v_axis] = v;
+
+              // blocks that aren't cubes are added separately
+              let state = section.get_block(local[0], local[1], local[2]);
+              if state == AIR_STATE || !models.is_full_cube(state) {
+                continue;
+              }
+
+              let pos = [local[0] as i32, y_offset + local[1] as i32, local[2] as i32];
+              let neighbour_pos = [
+                pos[0] + face.normal[0],
+                pos[1] + face.normal[1],
+                pos[2] + face.normal[2]
+              ];
+
+              if is_face_hidden(models, state, area.get_block(neighbour_pos)) {
+                continue;
+              }
+
+              // faces where biomes blend into each other are different at each corner, so they
+              // can't be merged
+              let tile = models.face_tile(state, face_index);
+              let tints = face_tints(area, models, state, face_index, pos);
+              let light = area.get_light(neighbour_pos);
+              if tints.iter().all(|tint| *tint == tints[0]) {
+                *cell = Some((tile, tints[0], light));
+              } else {
+                mesh.push_quad([chunk.x * 16 + pos[0], pos[1], chunk.z * 16 + pos[2]], [1, 1, 1], face, tile, tints, light);
+              }
+
+            }
+
+          }
+
+          // grow each face as wide as possible along u, then as tall as possible along v
+          for v in 0..16 {
+
+            let mut u = 0;
+            while u < 16 {
+
+              let Some(key @ (tile, tint, light)) = mask[v][u] else {
+                u += 1;
+                continue;
+              };
+
+              let mut width = 1;
+              while u + width < 16 && mask[v][u + width] == Some(key) {
+                width += 1;
+              }
+
+              let mut height = 1;
+              while v + height < 16 && mask[v + height][u..u + width].iter().all(|cell| *cell == Some(key)) {
+                height += 1;
+              }
+
+              // the merged faces can't be used again
+              mask[v..v + height].iter_mut().for_each(|row| row[u..u + width].fill(None));
+
+              let mut origin = [chunk.x * 16, y_offset, chunk.z * 16];
+              origin[axis] += layer as i32;
+              origin[u_axis] += u as i32;
+              origin[v_axis] += v as i32;
+
+              let mut size = [1; 3];
+              size[u_axis] = width as i32;
+              size[v_axis] = height as i32;
+
+              mesh.push_quad(origin, size, face, tile, [tint; 4], light);
+              u += width;
+
+            }
+
+          }
+
+        }
+
+      }
+
+      // blocks that aren't cubes can't be merged, so they're added one at a time
+      for y in 0..16 {
+
+        for z in 0..16 {
+
+          for x in 0..16 {
+
+            let state = section.get_block(x, y, z);
+            if state != AIR_STATE && !models.is_full_cube(state) {
+              mesh.push_model(area, models, chunk, [x as i32, y_offset + y as i32, z as i32], state);
+            }
+
+          }
+
+        }
+
+      }
+
+    }
+
+    mesh
+
+  }
+
+  /**
+   * add the quads of a block that isn't a full cube, at a position within the chunk. quads on the
+   * edge of the block are left out when the neighbour on that side covers them
+   */
+  fn push_model (&mut self, area: &ChunkArea, models: &BlockModels, chunk: &Chunk, pos: [i32; 3], state: u32) {
+
+    for quad in models.quads(state) {
+
+      // quads on the edge of the block are lit by the block they face, and the rest by the block
+      // itself, since light reaches inside blocks that aren't full cubes
+      let mut light_pos = pos;
+      if let Some(cullface) = quad.cullface {
+        let normal = FACES[cullface].normal;
+        light_pos = [pos[0] + normal[0], pos[1] + normal[1], pos[2] + normal[2]];
+        if is_face_hidden(models, state, area.get_block(light_pos)) {
+          continue;
+        }
+      }
+
+      let tints = if quad.tinted {
+        quad.corners.map(|corner| corner_tint(area, models, models.tint(state), pos, corner))
+      } else {
+        [WHITE; 4]
+      };
+      self.push_model_quad([chunk.x * 16 + pos[0], pos[1], chunk.z * 16 + pos[2]], quad, tints, area.get_light(light_pos));
+
+    }
+
+  }
+
+  /**
+   * add a quad of a block model to the mesh, for the block at a world position, tinted a colour
+   * at each corner and lit by a light level
+   */
+  fn push_model_quad (&mut self, pos: [i32; 3], quad: &ModelQuad, tints: [u32; 4], light: Light) {
+
+    let start = self.vertices.len() as u32;
+
+    for ((corner, tex_coords), tint) in quad.corners.iter().zip(quad.tex_coords).zip(tints) {
+      self.vertices.push(Vertex {
+        pos: [
+          pos[0] as f32 + corner[0],
+          pos[1] as f32 + corner[1],
+          pos[2] as f32 + corner[2]
+        ],
+        tex_coords,
+        normal: quad.normal,
+        tile: quad.tile,
+        tint: linear_colour(tint),
+        light: [light.block as f32, light.sky as f32]
+      });
+    }
+
+    self.indices.extend_from_slice(&[start, start + 1, start + 2, start, start + 2, start + 3]);
+
+  }
+
+  /**
+   * add a face covering size blocks, starting from the block at a world position, to the mesh,
+   * drawn with a tile of the atlas, tinted a colour at each corner and lit by a light level
+   */
+  fn push_quad (&mut self, pos: [i32; 3], size: [i32; 3], face: &Face, tile: u32, tints: [u32; 4], light: Light) {
+
+    let start = self.vertices.len() as u32;
+    let normal = face.normal.map(|n| n as f32);
+
+    // the axes going across and up the face, so the texture repeats once per block
+    let across = (0..3).find(|i| face.corners[1][*i] != face.corners[0][*i]).unwrap();
+    let up = (0..3).find(|i| face.corners[3][*i] != face.corners[0][*i]).unwrap();
+
+    for ((corner, tex_coords), tint) in face.corners.iter().zip(FACE_TEX_COORDS).zip(tints) {
+      self.vertices.push(Vertex {
+        pos: [
+          pos[0] as f32 + corner[0] * size[0] as f32,
+          pos[1] as f32 + corner[1] * size[1] as f32,
+          pos[2] as f32 + corner[2] * size[2] as f32
+        ],
+        tex_coords: [tex_coords[0] * size[across] as f32, tex_coords[1] * size[up] as f32],
+        normal,
+        tile,
+        tint: linear_colour(tint),
+        light: [light.block as f32, light.sky as f32]
+      });
+    }
+
+    self.indices.extend_from_slice(&[start, start + 1, start + 2, start, start + 2, start + 3]);
+
+  }
+
+}
+
+/**
+ * check whether a face of a block is covered by its neighbour, which has to be a full cube
+ */
+fn is_face_hidden (models: &BlockModels, state: u32, neighbour: u32) -> bool {
+
+  if neighbour == AIR_STATE || !models.is_full_cube(neighbour) {
+    return false;
+  }
+
+  // faces between two of the same see-through block, like water, aren't drawn either. blocks we
+  // don't know are only the same when their states are, so red glass still shows next to blue
+  let block = models.block(state);
+  let other = models.block(neighbour);
+  !models.is_transparent(neighbour) || (other == block && (block != blocks::UNKNOWN || state == neighbour))
+
+}
+
+/**
+ * get the tint at each corner of a face of a full cube block, at a position within the chunk
+ */
+fn face_tints (area: &ChunkArea, models: &BlockModels, state: u32, face: usize, pos: [i32; 3]) -> [u32; 4] {
+
+  if !models.is_face_tinted(state, face) {
+    return [WHITE; 4];
+  }
+  let tint = models.tint(state);
+  FACES[face].corners.map(|corner| corner_tint(area, models, tint, pos, corner))
+
+}
+
+/**
+ * get the colour of a tint at a corner of a block, at a position within the chunk. the colours of
+ * the biomes in the columns of blocks around the corner are averaged, so they fade smoothly from
+ * one biome into the next
+ */
+fn corner_tint (area: &ChunkArea, models: &BlockModels, tint: Tint, pos: [i32; 3], corner: [f32; 3]) -> u32 {
+
+  if matches!(tint, Tint::None | Tint::Fixed(_)) {
+    return models.tint_colour(tint, 0);
+  }
+
+  let x = pos[0] + corner[0].round() as i32;
+  let z = pos[2] + corner[2].round() as i32;
+  let mut sum = [0; 3];
+  for dx in -BLEND_RADIUS..BLEND_RADIUS {
+    for dz in -BLEND_RADIUS..BLEND_RADIUS {
+      let colour = models.tint_colour(tint, area.get_biome([x + dx, pos[1], z + dz]));
+      sum = [sum[0] + (colour >> 16 & 0xff), sum[1] + (colour >> 8 & 0xff), sum[2] + (colour & 0xff)];
+    }
+  }
+
+  let count = (BLEND_RADIUS * 2).pow(2) as u32;
+  let [r, g, b] = sum.map(|channel| (channel + count / 2) / count);
+  r << 16 | g << 8 | b
+
+}
+
+/**
+ * turn a 0xrrggbb colour into the linear rgb the shader works in, the same as the atlas texture is
+ * turned into when it's sampled
+ */
+fn linear_colour (colour: u32) -> [f32; 3] {
+  [colour >> 16, colour >> 8, colour].map(|channel| ((channel & 0xff) as f32 / 255.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::blocks::BlockState;
+  use crate::texture::Atlas;
+  use crate::world::{World, WorldHeight};
+  use image::{Rgba, RgbaImage};
+  use std::collections::BTreeMap;
+  use std::sync::Arc;
+
+  /**
+   * mesh the chunk at 0, 0 of a world in both modes, returning how many quads each made
+   */
+  fn quad_counts (world: &World) -> (usize, usize) {
+    let atlas = Atlas::new(["stone", "dirt", "moss_block"].iter().enumerate().map(|(i, name)| {
+      (name.to_string(), RgbaImage::from_pixel(2, 2, Rgba([i as u8 * 80, 100, 50, 255])))
+    }).collect());
+    quad_counts_with(world, &atlas)
+  }
+
+  /**
+   * mesh the chunk at 0, 0 of a world in both modes with the textures in an atlas
+   */
+  fn quad_counts_with (world: &World, atlas: &Atlas) -> (usize, usize) {
+
+    let mut models = BlockModels::new(None);
+    models.update(&world.states, &world.biomes, atlas);
+
+    let area = world.chunk_area((0, 0)).unwrap();
+    let culled = Mesh::from_chunk(&area, &models, MeshMode::Culled);
+    let greedy = Mesh::from_chunk(&area, &models, MeshMode::Greedy);
+    assert_eq!(culled.indices.len(), culled.vertices.len() / 4 * 6);
+    assert_eq!(greedy.indices.len(), greedy.vertices.len() / 4 * 6);
+    (culled.vertices.len() / 4, greedy.vertices.len() / 4)
+
+  }
+
+  /**
+   * create a world with a single chunk, filled by a function from each block position to the name
+   * of the block there
+   */
+  fn world_with (fill: impl Fn(usize, i32, usize) -> Option<&'static str>) -> World {
+
+    let mut world = World::test();
+    let mut chunk = Chunk::new(0, 0, WorldHeight::TALL);
+    for y in 0..16 {
+      for z in 0..16 {
+        for x in 0..16 {
+          if let Some(name) = fill(x, y, z) {
+            chunk.set_block(x, y, z, world.states.register(BlockState::new(name, BTreeMap::new()))).unwrap();
+          }
+        }
+      }
+    }
+    world.loaded_chunks.insert((0, 0), Arc::new(chunk));
+    world
+
+  }
+
+  #[test]
+  fn meshes_test_chunk () {
+
+    // a layer of moss with one more block on top: the layer's top less the covered face, its
+    // bottom, its four edges, and the extra block's top and sides
+    let (culled, greedy) = quad_counts(&World::test());
+    assert_eq!(culled, 255 + 256 + 64 + 5);
+
+    // the layer's top is split into four around the extra block, and everything else is a quad
+    assert_eq!(greedy, 4 + 1 + 4 + 5);
+    assert!(greedy < culled);
+
+  }
+
+  #[test]
+  fn merges_solid_slab () {
+
+    let world = world_with(|_, y, _| (y < 4).then_some("stone"));
+    let (culled, greedy) = quad_counts(&world);
+    assert_eq!(culled, 256 + 256 + 4 * 16 * 4);
+    assert_eq!(greedy, 6);
+
+  }
+
+  #[test]
+  fn keeps_different_tiles_apart () {
+
+    // stone on the west half and dirt on the east half of a layer
+    let world = world_with(|x, y, _| (y == 0).then_some(if x < 8 { "stone" } else { "dirt" }));
+    let (culled, greedy) = quad_counts(&world);
+    assert_eq!(culled, 256 * 2 + 64);
+
+    // the top and bottom are a quad for each half, as are the south and north edges, while the east
+    // and west edges are all one block
+    assert_eq!(greedy, 2 + 2 + 1 + 1 + 2 + 2);
+
+  }
+
+  #[test]
+  fn draws_faces_behind_stained_glass () {
+
+    // stone with glass to its east, which isn't a block it knows, so the stone's east face still
+    // shows through it, while the glass's west face is covered by the stone
+    let world = world_with(|x, y, z| match (x, y, z) {
+      (0, 0, 0) => Some("stone"),
+      (1, 0, 0) => Some("red_stained_glass"),
+      _ => None
+    });
+    assert_eq!(quad_counts(&world), (6 + 5, 6 + 5));
+
+    // the faces between two glass blocks are only hidden when they're the same colour
+    let pair = |east| world_with(move |x, y, z| match (x, y, z) {
+      (0, 0, 0) => Some("red_stained_glass"),
+      (1, 0, 0) => Some(east),
+      _ => None
+    });
+    assert_eq!(quad_counts(&pair("red_stained_glass")).0, 5 + 5);
+    assert_eq!(quad_counts(&pair("blue_stained_glass")).0, 6 + 6);
+
+  }
+
+  #[test]
+  fn draws_faces_behind_translucent_textures () {
+
+    // glowstone is usually solid, but not with a texture that has holes in it
+    let world = world_with(|x, y, z| match (x, y, z) {
+      (0, 0, 0) => Some("stone"),
+      (1, 0, 0) => Some("glowstone"),
+      _ => None
+    });
+    let mut glowstone = RgbaImage::from_pixel(2, 2, Rgba([200, 150, 50, 255]));
+    glowstone.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+    let atlas = Atlas::new(vec![
+      (String::from("stone"), RgbaImage::from_pixel(2, 2, Rgba([100, 100, 100, 255]))),
+      (String::from("glowstone"), glowstone)
+    ]);
+    assert_eq!(quad_counts_with(&world, &atlas).0, 6 + 5);
+
+    // and with an opaque one it hides the stone's face like any other block
+    assert_eq!(quad_counts(&world).0, 5 + 5);
+
+  }
+
+  #[test]
+  fn blends_corners_the_same_from_every_chunk () {
+
+    // four chunks around the corner at x 16, z 16, each in a different biome
+    let mut world = World::test();
+    let names = ["minecraft:plains", "minecraft:desert", "minecraft:swamp", "minecraft:jungle"];
+    for (i, pos) in [(0, 0), (1, 0), (0, 1), (1, 1)].into_iter().enumerate() {
+      let biome = world.biomes.register(names[i]);
+      let mut chunk = Chunk::new(pos.0, pos.1, WorldHeight::TALL);
+      chunk.biomes.fill([biome; 64]);
+      world.loaded_chunks.insert(pos, Arc::new(chunk));
+    }
+    let mut models = BlockModels::new(None);
+    models.update(&world.states, &world.biomes, &Atlas::new(vec![]));
+
+    // the same corner, seen from a block in each of the chunks
+    let tints: Vec<u32> = [
+      ((0, 0), [15, 1, 15], [1.0, 1.0, 1.0]),
+      ((1, 0), [0, 1, 15], [0.0, 1.0, 1.0]),
+      ((0, 1), [15, 1, 0], [1.0, 1.0, 0.0]),
+      ((1, 1), [0, 1, 0], [0.0, 1.0, 0.0])
+    ].into_iter()
+      .map(|(pos, block, corner)| corner_tint(&world.chunk_area(pos).unwrap(), &models, Tint::Grass, block, corner))
+      .collect();
+    assert!(tints.iter().all(|tint| *tint == tints[0]), "{:06x?}", tints);
+
+    // and it's a blend of all four
+    for name in names {
+      assert_ne!(tints[0], models.tint_colour(Tint::Grass, world.biomes.register(name)));
+    }
+
+  }
+
+}
@@ -0,0 +1,487 @@
+use std::sync::Arc;
+
+/*
+ * works out how each block state is drawn, either from a resource pack's block models or from
+ * textures named after the blocks. full cubes are described by the texture of each face so the
+ * mesher can merge them, anything else is baked into a list of quads. it also works out the
+ * colour each biome tints blocks like grass
+ */
+use crate::biomes::{BiomeColours, BiomeRegistry, Tint};
+use crate::blocks::{self, BlockState, StateRegistry};
+use crate::mesh::{FACES, FACE_NAMES};
+use crate::resources::{Element, ElementFace, ElementRotation, Model, ResourcePack, Variant};
+use crate::texture::Atlas;
+
+/**
+ * a single face of a block model, positioned within the block from 0 to 1
+ */
+#[derive(Clone, Debug)]
+pub struct ModelQuad {
+  pub corners: [[f32; 3]; 4], // bottom left, bottom right, top right, top left
+  pub tex_coords: [[f32; 2]; 4],
+  pub normal: [f32; 3],
+  pub tile: u32,
+  pub cullface: Option<usize>, // the face whose neighbour hides this quad, if any
+  pub tinted: bool
+}
+
+/**
+ * how a single block state is drawn
+ */
+#[derive(Clone)]
+struct StateModel {
+  block: u32, // the id of the block the state is of
+  faces: [u32; 6], // the atlas tile of each face, when it's a full cube
+  tinted: [bool; 6], // which faces are tinted, when it's a full cube
+  quads: Option<Vec<ModelQuad>>, // the faces of any other shape
+  transparent: bool // whether blocks behind it can be seen through it
+}
+
+/**
+ * the models of every block state in the world. each model is shared rather than copied when it's
+ * cloned, so a new copy can be made cheaply when states are added while chunks are still being
+ * meshed in the background with the old one
+ */
+#[derive(Clone)]
+pub struct BlockModels {
+  pack: Option<Arc<ResourcePack>>,
+  states: Vec<Arc<StateModel>>, // indexed by block state
+  colours: Vec<BiomeColours> // indexed by biome
+}
+
+impl BlockModels {
+
+  /**
+   * create an empty set of models, resolved from a resource pack if there is one
+   */
+  pub fn new (pack: Option<ResourcePack>) -> BlockModels {
+    BlockModels {
+      pack: pack.map(Arc::new),
+      states: vec![],
+      colours: vec![]
+    }
+  }
+
+  /**
+   * work out how to draw any block states, and the colours of any biomes, registered since the
+   * last update
+   */
+  pub fn update (&mut self, states: &StateRegistry, biomes: &BiomeRegistry, atlas: &Atlas) {
+
+    for state in self.states.len()..states.len() {
+      let state = states.get(state as u32);
+      let mut model = match &self.pack {
+        Some(pack) => pack_model(pack, state, atlas),
+        None => StateModel {
+          block: state.id,
+          faces: named_faces(state, atlas),
+          tinted: named_tinted_faces(state),
+          quads: None,
+          transparent: blocks::is_transparent(state.id)
+        }
+      };
+      // cubes with see-through textures, like glass from a resource pack, don't hide what's behind
+      model.transparent |= model.quads.is_none() && model.faces.iter().any(|tile| atlas.is_translucent(*tile));
+      self.states.push(Arc::new(model));
+    }
+
+    // biomes are coloured from the pack's colormaps, if it has them
+    let colormap = |name: &str| self.pack.as_ref().and_then(|pack| pack.colormaps.get(name));
+    let (grass, foliage) = (colormap("grass"), colormap("foliage"));
+    let colours: Vec<BiomeColours> = (self.colours.len()..biomes.len())
+      .map(|biome| biomes.get(biome as u16).colours(grass, foliage))
+      .collect();
+    self.colours.extend(colours);
+
+  }
+
+  /**
+   * check whether every block state and biome in the registries has a model or colours already
+   */
+  pub fn is_up_to_date (&self, states: &StateRegistry, biomes: &BiomeRegistry) -> bool {
+    self.states.len() >= states.len() && self.colours.len() >= biomes.len()
+  }
+
+  /**
+   * forget every block state and biome, for when the world is swapped for one with different
+   * registries
+   */
+  pub fn clear (&mut self) {
+    self.states.clear();
+    self.colours.clear();
+  }
+
+  /**
+   * get the id of the block a block state is of
+   */
+  pub fn block (&self, state: u32) -> u32 {
+    self.states.get(state as usize).map(|model| model.block).unwrap_or(blocks::UNKNOWN)
+  }
+
+  /**
+   * get the colour a block state is tinted
+   */
+  pub fn tint (&self, state: u32) -> Tint {
+    blocks::tint(self.block(state))
+  }
+
+  /**
+   * check whether a face of a full cube block state is tinted
+   */
+  pub fn is_face_tinted (&self, state: u32, face: usize) -> bool {
+    self.states.get(state as usize).is_some_and(|model| model.tinted[face])
+  }
+
+  /**
+   * get the colour a tint has in a biome, as 0xrrggbb
+   */
+  pub fn tint_colour (&self, tint: Tint, biome: u16) -> u32 {
+
+    let colours = self.colours.get(biome as usize);
+    match tint {
+      Tint::None => 0xffffff,
+      Tint::Fixed(colour) => colour,
+      Tint::Grass => colours.map(|colours| colours.grass).unwrap_or(0xffffff),
+      Tint::Foliage => colours.map(|colours| colours.foliage).unwrap_or(0xffffff),
+      Tint::Water => colours.map(|colours| colours.water).unwrap_or(0xffffff)
+    }
+
+  }
+
+  /**
+   * get the atlas tile a face of a full cube block state is drawn with
+   */
+  pub fn face_tile (&self, state: u32, face: usize) -> u32 {
+    self.states.get(state as usize).map(|model| model.faces[face]).unwrap_or(Atlas::MISSING)
+  }
+
+  /**
+   * check whether a block state is drawn as a full cube
+   */
+  pub fn is_full_cube (&self, state: u32) -> bool {
+    self.states.get(state as usize).is_none_or(|model| model.quads.is_none())
+  }
+
+  /**
+   * check whether blocks behind a block state can be seen through it
+   */
+  pub fn is_transparent (&self, state: u32) -> bool {
+    self.states.get(state as usize).is_none_or(|model| model.transparent)
+  }
+
+  /**
+   * get the quads of a block state that isn't a full cube
+   */
+  pub fn quads (&self, state: u32) -> &[ModelQuad] {
+    self.states.get(state as usize).and_then(|model| model.quads.as_deref()).unwrap_or(&[])
+  }
+
+}
+
+/**
+ * work out how to draw a block state from its models in a resource pack
+ */
+fn pack_model (pack: &ResourcePack, state: &BlockState, atlas: &Atlas) -> StateModel {
+
+  let models: Vec<(Variant, Model)> = pack.variants(&state.name, &state.properties).into_iter()
+    .filter_map(|variant| pack.model(&variant.model).map(|model| (variant, model)))
+    .collect();
+
+  // blocks with a single cube model, that isn't turned on its side, can be merged with others
+  if let [(variant, model)] = models.as_slice() {
+    if variant.x.rem_euclid(360) == 0 && is_cube_model(model) {
+      return StateModel {
+        block: state.id,
+        faces: cube_faces(variant, model, atlas),
+        tinted: cube_tinted_faces(variant, model),
+        quads: None,
+        transparent: blocks::is_transparent(state.id)
+      };
+    }
+  }
+
+  let quads: Vec<ModelQuad> = models.iter()
+    .flat_map(|(variant, model)| bake_model(variant, model, atlas))
+    .collect();
+
+  // blocks drawn some other way, like water or chests, are shown as cubes of their particle texture
+  if quads.is_empty() {
+    let particle = models.first()
+      .and_then(|(_, model)| model.texture("#particle"))
+      .and_then(|texture| atlas.tile(&texture))
+      .unwrap_or(Atlas::MISSING);
+    return StateModel {
+      block: state.id,
+      faces: [particle; 6],
+      tinted: [blocks::tint(state.id) != Tint::None; 6],
+      quads: None,
+      transparent: blocks::is_transparent(state.id)
+    };
+  }
+
+  StateModel {
+    block: state.id,
+    faces: [Atlas::MISSING; 6],
+    tinted: [false; 6],
+    quads: Some(quads),
+    transparent: true
+  }
+
+}
+
+/**
+ * check whether a model is a single box filling the whole block, with the whole texture on every
+ * face
+ */
+fn is_cube_model (model: &Model) -> bool {
+
+  let [element] = model.elements.as_slice() else {
+    return false;
+  };
+
+  element.from == [0.0; 3] && element.to == [16.0; 3] && element.rotation.is_none()
+    && FACE_NAMES.iter().all(|name| element.faces.get(*name).is_some_and(|face| {
+      face.uv.is_none_or(|uv| uv == [0.0, 0.0, 16.0, 16.0]) && face.rotation == 0
+    }))
+
+}
+
+/**
+ * find the textures of each face of a cube model
+ */
+fn cube_faces (variant: &Variant, model: &Model, atlas: &Atlas) -> [u32; 6] {
+
+  std::array::from_fn(|face| {
+    let direction = model_direction(variant, FACE_NAMES[face]);
+    model.elements.iter()
+      .find_map(|element| element.faces.get(direction))
+      .and_then(|face| model.texture(&face.texture))
+      .and_then(|texture| atlas.tile(&texture))
+      .unwrap_or(Atlas::MISSING)
+  })
+
+}
+
+/**
+ * find which faces of a cube model are tinted
+ */
+fn cube_tinted_faces (variant: &Variant, model: &Model) -> [bool; 6] {
+
+  std::array::from_fn(|face| {
+    let direction = model_direction(variant, FACE_NAMES[face]);
+    model.elements.iter()
+      .find_map(|element| element.faces.get(direction))
+      .is_some_and(|face| face.tintindex.is_some())
+  })
+
+}
+
+/**
+ * turn each face of each element of a model into a quad, turned by the variant's rotation
+ */
+fn bake_model (variant: &Variant, model: &Model, atlas: &Atlas) -> Vec<ModelQuad> {
+
+  let mut quads = vec![];
+
+  for element in model.elements.iter() {
+
+    for (index, face) in FACES.iter().enumerate() {
+
+      let Some(element_face) = element.faces.get(FACE_NAMES[index]) else {
+        continue;
+      };
+
+      let tile = model.texture(&element_face.texture)
+        .and_then(|texture| atlas.tile(&texture))
+        .unwrap_or(Atlas::MISSING);
+
+      // the corners of the face on the element's box, from 0 to 1 across the block
+      let corners = face.corners.map(|corner| std::array::from_fn(|axis| {
+        let pixel = if corner[axis] == 0.0 { element.from[axis] } else { element.to[axis] };
+        pixel / 16.0
+      }));
+      let normal = face.normal.map(|n| n as f32);
+
+      // turn the element around its own origin, then the whole model around the block centre
+      let (corners, normal) = match &element.rotation {
+        Some(rotation) => (
+          corners.map(|corner| rotate_element_point(corner, rotation, true)),
+          rotate_element_point(normal, rotation, false)
+        ),
+        None => (corners, normal)
+      };
+      let corners = corners.map(|corner| rotate_variant_point(corner, variant, true));
+      let normal = rotate_variant_point(normal, variant, false);
+
+      let cullface = element_face.cullface.as_deref()
+        .and_then(|direction| FACE_NAMES.iter().position(|name| *name == rotate_direction(direction, variant.x, variant.y)));
+
+      quads.push(ModelQuad {
+        corners,
+        tex_coords: face_tex_coords(element, element_face, index),
+        normal,
+        tile,
+        cullface,
+        tinted: element_face.tintindex.is_some()
+      });
+
+    }
+
+  }
+
+  quads
+
+}
+
+/**
+ * get the texture coordinates of each corner of an element's face, as a fraction of the texture.
+ * faces without a uv of their own use the part of the texture that lines up with the element
+ */
+fn face_tex_coords (element: &Element, face: &ElementFace, index: usize) -> [[f32; 2]; 4] {
+
+  let (from, to) = (element.from, element.to);
+  let [u1, v1, u2, v2] = face.uv.unwrap_or(match FACE_NAMES[index] {
+    "east" => [16.0 - to[2], 16.0 - to[1], 16.0 - from[2], 16.0 - from[1]],
+    "west" => [from[2], 16.0 - to[1], to[2], 16.0 - from[1]],
+    "up" => [from[0], from[2], to[0], to[2]],
+    "down" => [from[0], 16.0 - to[2], to[0], 16.0 - from[2]],
+    "south" => [from[0], 16.0 - to[1], to[0], 16.0 - from[1]],
+    _ => [16.0 - to[0], 16.0 - to[1], 16.0 - from[0], 16.0 - from[1]]
+  });
+
+  // bottom left, bottom right, top right, top left, moved round a corner for each quarter turn
+  let uvs = [[u1, v2], [u2, v2], [u2, v1], [u1, v1]];
+  let turns = (face.rotation / 90).rem_euclid(4) as usize;
+  std::array::from_fn(|corner| uvs[(corner + turns) % 4].map(|uv| uv / 16.0))
+
+}
+
+/**
+ * turn a point, or a direction if it isn't a point, by an element's rotation
+ */
+fn rotate_element_point (point: [f32; 3], rotation: &ElementRotation, is_point: bool) -> [f32; 3] {
+
+  let axis = match rotation.axis.as_str() {
+    "x" => 0,
+    "y" => 1,
+    _ => 2
+  };
+  // the two axes that turn, in the order that makes a positive angle anticlockwise
+  let (a, b) = match axis {
+    0 => (1, 2),
+    1 => (2, 0),
+    _ => (0, 1)
+  };
+
+  let origin = if is_point { rotation.origin.map(|pixel| pixel / 16.0) } else { [0.0; 3] };
+  let angle = rotation.angle.to_radians();
+  let (sin, cos) = angle.sin_cos();
+  // rescaled elements are stretched so they still reach the edges of the block
+  let scale = if rotation.rescale && is_point { 1.0 / cos } else { 1.0 };
+
+  let relative = [point[0] - origin[0], point[1] - origin[1], point[2] - origin[2]];
+  let mut rotated = relative;
+  rotated[a] = (relative[a] * cos - relative[b] * sin) * scale;
+  rotated[b] = (relative[a] * sin + relative[b] * cos) * scale;
+
+  [rotated[0] + origin[0], rotated[1] + origin[1], rotated[2] + origin[2]]
+
+}
+
+/**
+ * turn a point, or a direction if it isn't a point, around the centre of the block by a
+ * variant's rotation, the same way as rotate_direction
+ */
+fn rotate_variant_point (point: [f32; 3], variant: &Variant, is_point: bool) -> [f32; 3] {
+
+  let centre = if is_point { 0.5 } else { 0.0 };
+  let [mut x, mut y, mut z] = point.map(|value| value - centre);
+
+  // up turns to north
+  for _ in 0..(variant.x / 90).rem_euclid(4) {
+    (y, z) = (z, -y);
+  }
+  // north turns to east
+  for _ in 0..(variant.y / 90).rem_euclid(4) {
+    (x, z) = (-z, x);
+  }
+
+  [x + centre, y + centre, z + centre]
+
+}
+
+/**
+ * find the textures of a block state from textures named after the block, looking for one with a
+ * suffix for that face first (like grass_block_top), then one named just after the block
+ */
+fn named_faces (state: &BlockState, atlas: &Atlas) -> [u32; 6] {
+
+  let name = blocks::block_name(state.id);
+
+  std::array::from_fn(|face| {
+    let suffixes: &[&str] = match FACE_NAMES[face] {
+      "up" => &["_top", ""],
+      "down" => &["_bottom", "_top", ""],
+      _ => &["_side", ""]
+    };
+    suffixes.iter()
+      .find_map(|suffix| atlas.tile(&format!("{}{}", name, suffix)))
+      .unwrap_or(Atlas::MISSING)
+  })
+
+}
+
+/**
+ * find which faces of a block state are tinted when it's drawn with textures named after the
+ * block. only the top of grass blocks is, since their sides are dirt
+ */
+fn named_tinted_faces (state: &BlockState) -> [bool; 6] {
+
+  let tinted = blocks::tint(state.id) != Tint::None;
+  let top_only = blocks::block_name(state.id) == "grass_block";
+  std::array::from_fn(|face| tinted && (!top_only || FACE_NAMES[face] == "up"))
+
+}
+
+/**
+ * find which direction of a model ends up facing a direction in the world once the variant's
+ * rotation is applied
+ */
+fn model_direction (variant: &Variant, direction: &'static str) -> &'static str {
+
+  FACE_NAMES.iter().copied()
+    .find(|model_direction| rotate_direction(model_direction, variant.x, variant.y) == direction)
+    .unwrap_or(direction)
+
+}
+
+/**
+ * turn a direction around the x axis and then the y axis, in steps of 90 degrees
+ */
+fn rotate_direction (direction: &str, x: i32, y: i32) -> &str {
+
+  let mut direction = direction;
+
+  for _ in 0..(x / 90).rem_euclid(4) {
+    direction = match direction {
+      "up" => "north",
+      "north" => "down",
+      "down" => "south",
+      "south" => "up",
+      other => other
+    };
+  }
+
+  for _ in 0..(y / 90).rem_euclid(4) {
+    direction = match direction {
+      "north" => "east",
+      "east" => "south",
+      "south" => "west",
+      "west" => "north",
+      other => other
+    };
+  }
+
+  direction
+
+}
@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+
+/*
+ * reads the command line options for which world to show and how to start off viewing it
+ */
+use crate::save::Dimension;
+
+pub const USAGE: &str = "\
+usage: webcraft <world> [options]
+
+  <world>                      a world save folder, or a single region (.mca) file
+
+options:
+  --dimension <name>           overworld, nether or end, for world saves (default overworld)
+  --pos <x,y,z>                start flying at a position in block coordinates, instead of at spawn
+  --rot <yaw,pitch>            start looking this way, in degrees as shown in game
+  --render-distance <chunks>   how many chunks away to draw (default 8)
+  --size <width>x<height>      the size of the window (default 1280x720)
+  --resource-pack <path>       load block models and textures from a resource pack folder or zip
+  --greedy                     merge block faces together when meshing
+  --help                       show this message";
+
+pub struct Options {
+  pub path: PathBuf,
+  pub dimension: Option<Dimension>, // only for world saves, which show the overworld otherwise
+  pub position: Option<[f32; 3]>,
+  pub rotation: Option<[f32; 2]>, // yaw and pitch in degrees, the same as in game
+  pub render_distance: u32, // in chunks
+  pub window_size: [u32; 2],
+  pub resource_pack: Option<PathBuf>,
+  pub greedy: bool
+}
+
+impl Options {
+
+  /**
+   * read the options from the command line arguments, not including the program name. asking for
+   * help is returned as an error too, so the usage gets printed
+   */
+  pub fn parse (args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+
+    let mut path = None;
+    let mut options = Options {
+      path: PathBuf::new(),
+      dimension: None,
+      position: None,
+      rotation: None,
+      render_distance: 8,
+      window_size: [1280, 720],
+      resource_pack: None,
+      greedy: false
+    };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+
+      // every option other than the flags is followed by its value
+      let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+
+      match arg.as_str() {
+        "--help" | "-h" => return Err(String::new()),
+        "--greedy" => options.greedy = true,
+        "--dimension" => {
+          let name = value(&arg)?;
+          options.dimension = Some(Dimension::from_name(&name)
+            .ok_or_else(|| format!("unknown dimension {}, expected overworld, nether or end", name))?);
+        }
+        "--pos" => options.position = Some(parse_numbers(&value(&arg)?, ',', &arg)?),
+        "--rot" => options.rotation = Some(parse_numbers(&value(&arg)?, ',', &arg)?),
+        "--render-distance" => {
+          let distance = value(&arg)?;
+          options.render_distance = distance.parse().ok().filter(|distance| *distance > 0)
+            .ok_or_else(|| format!("--render-distance should be a whole number of chunks, not {}", distance))?;
+        }
+        "--size" => {
+          let size = value(&arg)?;
+          options.window_size = parse_numbers(&size, 'x', &arg)?;
+          if options.window_size.contains(&0) {
+            return Err(format!("--size needs a width and height above 0, not {}", size));
+          }
+        }
+        "--resource-pack" => options.resource_pack = Some(PathBuf::from(value(&arg)?)),
+        _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+        _ if path.is_none() => path = Some(PathBuf::from(arg)),
+        _ => return Err(format!("only one world can be opened, but {} was given too", arg))
+      }
+
+    }
+
+    options.path = path.ok_or("no world was given")?;
+    Ok(options)
+
+  }
+
+}
+
+/**
+ * read a fixed number of values separated by a character, like 1,64,-20 or 1280x720
+ */
+fn parse_numbers<T: std::str::FromStr, const N: usize> (text: &str, separator: char, option: &str) -> Result<[T; N], String> {
+
+  let error = || format!("{} expects {} numbers separated by '{}', not {}", option, N, separator, text);
+
+  let values: Vec<T> = text.split(separator)
+    .map(|value| value.trim().parse().map_err(|_| error()))
+    .collect::<Result<_, _>>()?;
+  values.try_into().map_err(|_| error())
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  /**
+   * parse options from a list of arguments
+   */
+  fn parse (args: &[&str]) -> Result<Options, String> {
+    Options::parse(args.iter().map(|arg| arg.to_string()))
+  }
+
+  #[test]
+  fn reads_every_option () {
+
+    let options = parse(&[
+      "saves/world", "--dimension", "nether", "--pos", "1,64.5,-20", "--rot", "90,-30",
+      "--render-distance", "4", "--size", "800x600", "--resource-pack", "pack.zip", "--greedy"
+    ]).unwrap();
+    assert_eq!(options.path, PathBuf::from("saves/world"));
+    assert_eq!(options.dimension, Some(Dimension::Nether));
+    assert_eq!(options.position, Some([1.0, 64.5, -20.0]));
+    assert_eq!(options.rotation, Some([90.0, -30.0]));
+    assert_eq!(options.render_distance, 4);
+    assert_eq!(options.window_size, [800, 600]);
+    assert_eq!(options.resource_pack, Some(PathBuf::from("pack.zip")));
+    assert!(options.greedy);
+
+    // and everything else has a default
+    let options = parse(&["r.0.0.mca"]).unwrap();
+    assert_eq!(options.dimension, None);
+    assert_eq!(options.position, None);
+    assert_eq!(options.render_distance, 8);
+    assert_eq!(options.window_size, [1280, 720]);
+    assert!(!options.greedy);
+
+  }
+
+  #[test]
+  fn needs_a_single_world () {
+    assert_eq!(parse(&[]).err().unwrap(), "no world was given");
+    assert_eq!(parse(&["--greedy"]).err().unwrap(), "no world was given");
+    assert!(parse(&["one", "two"]).is_err());
+  }
+
+  #[test]
+  fn rejects_unknown_options () {
+    assert_eq!(parse(&["world", "--fast"]).err().unwrap(), "unknown option --fast");
+    assert!(parse(&["world", "--dimension", "moon"]).is_err());
+    assert_eq!(parse(&["world", "--pos"]).err().unwrap(), "--pos needs a value");
+  }
+
+  #[test]
+  fn rejects_the_wrong_number_of_values () {
+    for args in [
+      ["--pos", "1,2"], ["--pos", "1,2,3,4"], ["--pos", "1,two,3"],
+      ["--rot", "90"], ["--rot", "90,0,0"],
+      ["--size", "800"], ["--size", "800x600x2"], ["--size", "800,600"]
+    ] {
+      assert!(parse(&["world", args[0], args[1]]).is_err(), "{:?} was accepted", args);
+    }
+  }
+
+  #[test]
+  fn rejects_nothing_to_show () {
+    for size in ["0", "-1", "many"] {
+      assert!(parse(&["world", "--render-distance", size]).is_err(), "{} was accepted", size);
+    }
+    for size in ["0x0", "0x720", "1280x0", "-1280x720"] {
+      assert!(parse(&["world", "--size", size]).is_err(), "{} was accepted", size);
+    }
+  }
+
+  #[test]
+  fn asks_for_help () {
+    // help is an empty error, before or after the world, and anything after it is ignored
+    assert_eq!(parse(&["--help"]).err(), Some(String::new()));
+    assert_eq!(parse(&["world", "-h"]).err(), Some(String::new()));
+    assert_eq!(parse(&["--help", "--fast"]).err(), Some(String::new()));
+  }
+
+}
@@ -0,0 +1,198 @@
+use std::io::Read;
+
+/*
+ * reads region files, which each hold the compressed nbt of a 32x32 grid of chunks after a header
+ * saying where each chunk is in the file
+ */
+use crate::world::WorldError;
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+// the header is a table of where each chunk is, then a table of when each was last saved
+const HEADER_SIZE: usize = 8192;
+// chunks are laid out in 4KiB sectors
+const SECTOR_SIZE: usize = 4096;
+
+/**
+ * a region file, read into memory
+ */
+pub struct Region {
+  data: Vec<u8>
+}
+
+impl Region {
+
+  /**
+   * read a whole region file
+   */
+  pub fn from_reader (reader: &mut impl Read) -> Result<Region, WorldError> {
+
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+
+    if data.len() < HEADER_SIZE {
+      return Err(WorldError::CorruptRegion(String::from("it's missing its header")));
+    }
+    Ok(Region { data })
+
+  }
+
+  /**
+   * get the compressed data of a chunk, from its position within the region. chunks that haven't
+   * been generated are None
+   */
+  pub fn get_chunk (&self, x: u32, z: u32) -> Result<Option<RegionChunk>, WorldError> {
+
+    // each location is the sector the chunk starts at, in three bytes, then how many sectors it takes up
+    let location = &self.data[(z as usize * 32 + x as usize) * 4..][..4];
+    let sector = u32::from_be_bytes([0, location[0], location[1], location[2]]) as usize;
+    if sector == 0 || location[3] == 0 {
+      return Ok(None);
+    }
+
+    let past_end = || WorldError::CorruptRegion(String::from("a chunk runs past the end of the file"));
+
+    // the chunk starts with its length, counting the byte after it saying how it's compressed
+    let start = sector * SECTOR_SIZE;
+    let header = self.data.get(start..start + 5).ok_or_else(past_end)?;
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    if length == 0 {
+      return Err(WorldError::CorruptRegion(String::from("a chunk has no data")));
+    }
+    let data = self.data.get(start + 5..start + 4 + length).ok_or_else(past_end)?;
+
+    Ok(Some(RegionChunk {
+      compression: header[4],
+      data: data.to_vec()
+    }))
+
+  }
+
+}
+
+/**
+ * the compressed nbt of a chunk, copied out of its region so it can be decompressed elsewhere
+ */
+pub struct RegionChunk {
+  compression: u8,
+  data: Vec<u8>
+}
+
+impl RegionChunk {
+
+  /**
+   * decompress the chunk's nbt
+   */
+  pub fn decompress (&self) -> Result<Vec<u8>, WorldError> {
+
+    let mut bytes = vec![];
+    let result = match self.compression {
+      1 => GzDecoder::new(self.data.as_slice()).read_to_end(&mut bytes),
+      2 => ZlibDecoder::new(self.data.as_slice()).read_to_end(&mut bytes),
+      3 => return Ok(self.data.clone()),
+      4 => return Err(WorldError::Decompression(String::from("LZ4 compression isn't supported"))),
+      // chunks too big for the region are kept in their own .mcc file next to it
+      compression if compression & 128 != 0 => {
+        return Err(WorldError::Decompression(String::from("the chunk is stored in a separate .mcc file, which isn't supported")));
+      }
+      compression => return Err(WorldError::Decompression(format!("unknown compression type {}", compression)))
+    };
+
+    result.map_err(|err| WorldError::Decompression(err.to_string()))?;
+    Ok(bytes)
+
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use flate2::write::ZlibEncoder;
+  use flate2::Compression;
+  use std::io::Write;
+
+  /**
+   * create a region file with the chunk at 0, 0 in the third sector, taking up one sector, and
+   * some bytes after the header
+   */
+  fn region_with (chunk: &[u8]) -> Vec<u8> {
+
+    let mut data = vec![0; HEADER_SIZE];
+    data[..4].copy_from_slice(&[0, 0, 2, 1]);
+    data.extend_from_slice(chunk);
+    data
+
+  }
+
+  /**
+   * get the chunk at 0, 0 of a region file made of some bytes
+   */
+  fn get_chunk (data: Vec<u8>) -> Result<Option<RegionChunk>, WorldError> {
+    Region::from_reader(&mut data.as_slice())?.get_chunk(0, 0)
+  }
+
+  #[test]
+  fn reads_compressed_chunks () {
+
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(b"some nbt").unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let mut chunk = ((compressed.len() + 1) as u32).to_be_bytes().to_vec();
+    chunk.push(2);
+    chunk.extend_from_slice(&compressed);
+    chunk.resize(SECTOR_SIZE, 0);
+
+    let region = Region::from_reader(&mut region_with(&chunk).as_slice()).unwrap();
+    let data = region.get_chunk(0, 0).unwrap().unwrap().decompress().unwrap();
+    assert_eq!(data, b"some nbt");
+
+    // chunks that haven't been generated have no location
+    assert!(region.get_chunk(1, 0).unwrap().is_none());
+    assert!(region.get_chunk(31, 31).unwrap().is_none());
+
+  }
+
+  #[test]
+  fn rejects_a_missing_header () {
+    assert!(matches!(get_chunk(vec![0; HEADER_SIZE - 1]), Err(WorldError::CorruptRegion(_))));
+  }
+
+  #[test]
+  fn rejects_truncated_chunks () {
+
+    // the chunk's sector is past the end of the file
+    assert!(matches!(get_chunk(region_with(&[])), Err(WorldError::CorruptRegion(_))));
+
+    // the chunk says it's longer than the rest of the file
+    let mut chunk = 100u32.to_be_bytes().to_vec();
+    chunk.extend_from_slice(&[2, 1, 2, 3]);
+    assert!(matches!(get_chunk(region_with(&chunk)), Err(WorldError::CorruptRegion(_))));
+
+  }
+
+  #[test]
+  fn rejects_empty_chunks () {
+
+    let mut chunk = vec![0, 0, 0, 0, 2];
+    chunk.resize(SECTOR_SIZE, 0);
+    assert!(matches!(get_chunk(region_with(&chunk)), Err(WorldError::CorruptRegion(_))));
+
+  }
+
+  #[test]
+  fn rejects_unsupported_compression () {
+
+    for compression in [4, 9, 130] {
+      let chunk = RegionChunk { compression, data: vec![1, 2, 3] };
+      assert!(matches!(chunk.decompress(), Err(WorldError::Decompression(_))));
+    }
+
+    // and data that isn't really compressed the way it says
+    let chunk = RegionChunk { compression: 2, data: vec![1, 2, 3] };
+    assert!(matches!(chunk.decompress(), Err(WorldError::Decompression(_))));
+
+  }
+
+}
@@ -2,77 +2,14 @@
 * manage the renderer
 */
 use std::borrow::Cow;
+use std::collections::HashMap;
 
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 
 use crate::camera::Camera;
-use crate::texture::Texture;
-
-// vertices for a cube
-const VERT: [f32; 192] = [
-  // pos              tx coords  normal
-  // front
-  -1.0, -1.0,  1.0,   1.0, 1.0,   0.0,  0.0, -1.0, // bottom left
-   1.0, -1.0,  1.0,   0.0, 1.0,   0.0,  0.0, -1.0, // bottom right
-   1.0,  1.0,  1.0,   0.0, 0.0,   0.0,  0.0, -1.0, // top right
-  -1.0,  1.0,  1.0,   1.0, 0.0,   0.0,  0.0, -1.0, // top left
-  // back
-  -1.0, -1.0, -1.0,   1.0, 1.0,   0.0,  0.0,  1.0, // bottom left
-   1.0, -1.0, -1.0,   0.0, 1.0,   0.0,  0.0,  1.0, // bottom right
-   1.0,  1.0, -1.0,   0.0, 0.0,   0.0,  0.0,  1.0, // top right
-  -1.0,  1.0, -1.0,   1.0, 0.0,   0.0,  0.0,  1.0, // top left
-  // bottom
-  -1.0, -1.0, -1.0,   1.0, 0.0,   0.0, -1.0,  0.0, // back left
-  -1.0, -1.0,  1.0,   0.0, 0.0,   0.0, -1.0,  0.0, // front left
-   1.0, -1.0,  1.0,   0.0, 1.0,   0.0, -1.0,  0.0, // front right
-   1.0, -1.0, -1.0,   1.0, 1.0,   0.0, -1.0,  0.0, // back right
-   // left
-  -1.0, -1.0, -1.0,   0.0, 1.0,  -1.0,  0.0,  0.0, // bottom back
-  -1.0,  1.0, -1.0,   0.0, 0.0,  -1.0,  0.0,  0.0, // top back
-  -1.0, -1.0,  1.0,   1.0, 1.0,  -1.0,  0.0,  0.0, // bottom front
-  -1.0,  1.0,  1.0,   1.0, 0.0,  -1.0,  0.0,  0.0, // top front
-  // right
-   1.0,  1.0, -1.0,   1.0, 0.0,   1.0,  0.0,  0.0, // top back
-   1.0, -1.0, -1.0,   1.0, 1.0,   1.0,  0.0,  0.0, // bottom back
-   1.0,  1.0,  1.0,   0.0, 0.0,   1.0,  0.0,  0.0, // top front
-   1.0, -1.0,  1.0,   0.0, 1.0,   1.0,  0.0,  0.0, // bottom front
-  // top
-   1.0,  1.0,  1.0,   1.0, 1.0,   0.0,  1.0,  0.0, // front right
-  -1.0,  1.0,  1.0,   0.0, 1.0,   0.0,  1.0,  0.0, // front left
-   1.0,  1.0, -1.0,   1.0, 0.0,   0.0,  1.0,  0.0, // back right
-  -1.0,  1.0, -1.0,   0.0, 0.0,   0.0,  1.0,  0.0, // back left
-
-
-];
-
-const INDEX: [u16; 36] = [ 
-
-  // back face
-  4, 5, 7,
-  5, 6, 7,
-
-  // bottom face
-  8, 9, 10,
-  8, 10, 11,
-
-  // left face
-  12, 13, 14,
-  13, 15, 14,
-
-  // right face
-  16, 17, 18,
-  17, 19, 18,
-
-  // top face
-  20, 21, 22,
-  21, 23, 22,
-
-  // front face
-  0, 1, 2,
-  0, 2, 3
-
-];
+use crate::mesh::Mesh;
+use crate::texture::{Atlas, Texture};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -93,11 +30,42 @@ impl Uniforms {
   }
 }
 
+/**
+ * the layout of the atlas, so the shader can find the tile for each face
+ */
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AtlasInfo {
+  columns: u32,
+  tile_size: u32,
+  padding: u32,
+  _padding: u32
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Instance {
-  pub pos: [i32; 3],
-  pub block: u32
+pub struct Vertex {
+  pub pos: [f32; 3],
+  pub tex_coords: [f32; 2],
+  pub normal: [f32; 3],
+  pub tile: u32,
+  pub tint: [f32; 3], // the colour the texture is multiplied by, in linear rgb
+  pub light: [f32; 2] // the block and sky light levels the face is lit by, from 0 to 15
+}
+
+/**
+ * the gpu buffers holding the mesh of a single chunk
+ */
+struct ChunkBuffers {
+  vbuf: wgpu::Buffer,
+  ibuf: wgpu::Buffer,
+  isize: u32,
+  // how many vertices and indices the buffers have room for
+  vcapacity: usize,
+  icapacity: usize,
+  // the corners of the box around the mesh, for skipping chunks outside the view
+  min: [f32; 3],
+  max: [f32; 3]
 }
 
 pub struct Render<'a> {
@@ -107,15 +75,13 @@ pub struct Render<'a> {
   queue: wgpu::Queue,
   pipeline: wgpu::RenderPipeline,
   config: wgpu::SurfaceConfiguration,
-  vbuf: wgpu::Buffer,
-  ibuf: wgpu::Buffer,
-  isize: u32,
-  instbuf: wgpu::Buffer,
-  instsize: u32,
+  chunks: HashMap<(i32, i32), ChunkBuffers>, // keyed by chunk x and z
+  frustum: [[f32; 4]; 6],
   ubuf: wgpu::Buffer,
   ubg: wgpu::BindGroup,
   tbg: wgpu::BindGroup,
   uniforms: Uniforms,
+  #[allow(dead_code)]
   texture: Texture,
   depth_texture: Texture,
   pub window: &'a winit::window::Window
@@ -127,7 +93,7 @@ impl<'a> Render<'a> {
   /**
   * create a new instance of render
   */
-  pub async fn new (window: &'a winit::window::Window, camera: &mut Camera, instances: Vec<Instance>) -> Render<'a> {
+  pub async fn new (window: &'a winit::window::Window, camera: &mut Camera, atlas: &Atlas) -> Render<'a> {
     
     // create the renderer
     let mut size = window.inner_size();
@@ -165,9 +131,22 @@ impl<'a> Render<'a> {
     .await
     .expect("Failed to create device");
 
-    // load the texture
-    let tx_bytes = include_bytes!("textures/moss.png");
-    let texture = Texture::from_bytes(&device, &queue, tx_bytes, "moss.png").unwrap();
+    // upload the atlas, along with its layout so the shader can find each tile
+    let atlas_image = image::DynamicImage::ImageRgba8(atlas.image.clone());
+    let texture = Texture::from_image(&device, &queue, &atlas_image, Some("block_atlas")).unwrap();
+
+    let abuf = device.create_buffer_init(
+      &wgpu::util::BufferInitDescriptor {
+        label: Some("Atlas Buffer"),
+        contents: bytemuck::cast_slice(&[AtlasInfo {
+          columns: atlas.columns,
+          tile_size: atlas.tile_size,
+          padding: Atlas::PADDING,
+          _padding: 0
+        }]),
+        usage: wgpu::BufferUsages::UNIFORM,
+      }
+    );
 
     let tbg_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
       entries: &[
@@ -189,6 +168,16 @@ impl<'a> Render<'a> {
           ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
           count: None,
         },
+        wgpu::BindGroupLayoutEntry {
+          binding: 2,
+          visibility: wgpu::ShaderStages::FRAGMENT,
+          ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+          },
+          count: None,
+        },
       ],
       label: Some("texture_bind_group_layout"),
     });
@@ -204,6 +193,10 @@ impl<'a> Render<'a> {
         wgpu::BindGroupEntry {
           binding: 1,
           resource: wgpu::BindingResource::Sampler(&texture.sampler), // CHANGED!
+        },
+        wgpu::BindGroupEntry {
+          binding: 2,
+          resource: abuf.as_entire_binding(),
         }
       ],
       label: Some("texture_bind_group"),
@@ -276,7 +269,7 @@ impl<'a> Render<'a> {
         buffers: &[
           // vertex buffer layout
           wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
               wgpu::VertexAttribute {
@@ -294,22 +287,20 @@ impl<'a> Render<'a> {
                 shader_location: 2,
                 format: wgpu::VertexFormat::Float32x3,
               },
-            ],
-          },
-          // instance buffer layout
-          wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
               wgpu::VertexAttribute {
-                offset: 0,
-                shader_location: 5,
-                format: wgpu::VertexFormat::Sint32x3,
+                offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Uint32,
               },
               wgpu::VertexAttribute {
-                offset: std::mem::size_of::<[i32; 3]>() as wgpu::BufferAddress,
-                shader_location: 6,
-                format: wgpu::VertexFormat::Uint32,
+                offset: std::mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                shader_location: 4,
+                format: wgpu::VertexFormat::Float32x3,
+              },
+              wgpu::VertexAttribute {
+                offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                shader_location: 5,
+                format: wgpu::VertexFormat::Float32x2,
               },
             ],
           }
@@ -326,7 +317,8 @@ impl<'a> Render<'a> {
       depth_stencil: Some(wgpu::DepthStencilState {
         format: Texture::DEPTH_FORMAT,
         depth_write_enabled: true,
-        depth_compare: wgpu::CompareFunction::Less,
+        // depth is reversed, with the near plane at 1 and far away at 0
+        depth_compare: wgpu::CompareFunction::Greater,
         stencil: wgpu::StencilState::default(),
         bias: wgpu::DepthBiasState::default()
       }),
@@ -335,24 +327,7 @@ impl<'a> Render<'a> {
       cache: None,
     });
 
-    let vbuf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-      label: Some("Vertex Buffer"),
-      contents: bytemuck::cast_slice(&VERT),
-      usage: wgpu::BufferUsages::VERTEX,
-    });
-    let ibuf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-      label: Some("Index Buffer"),
-      contents: bytemuck::cast_slice(&INDEX),
-      usage: wgpu::BufferUsages::INDEX,
-    });
-    let instbuf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-      label: Some("Instance Buffer"),
-      contents: bytemuck::cast_slice(&instances),
-      usage: wgpu::BufferUsages::VERTEX,
-    });
-
-    let isize = INDEX.len() as u32;
-    let instsize = instances.len() as u32;
+    let frustum = camera.frustum_planes();
     
     let config = surface
     .get_default_config(&adapter, size.width, size.height)
@@ -362,7 +337,7 @@ impl<'a> Render<'a> {
     // create depth texture
     let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
     
-    Render { surface, device, queue, pipeline: render_pipeline, config, vbuf, ibuf, isize, instbuf, instsize, ubuf, ubg, tbg, uniforms, texture, depth_texture, window }
+    Render { surface, device, queue, pipeline: render_pipeline, config, chunks: HashMap::new(), frustum, ubuf, ubg, tbg, uniforms, texture, depth_texture, window }
     
   }
   
@@ -386,13 +361,79 @@ impl<'a> Render<'a> {
    */
   pub fn update_camera (&mut self, camera: &Camera) {
     self.uniforms.update_view_proj(camera);
+    self.frustum = camera.frustum_planes();
     self.queue.write_buffer(&self.ubuf, 0, bytemuck::cast_slice(&[self.uniforms]));
   }
 
-  pub fn update_instances (&mut self, instances: Vec<Instance>) {
+  /**
+   * upload the mesh of a chunk, replacing whatever was there before. the chunk's existing buffers
+   * are written over if they're big enough, otherwise new ones are made
+   */
+  pub fn upload_chunk (&mut self, pos: (i32, i32), mesh: &Mesh) {
+
+    // there's nothing to draw for an empty mesh
+    if mesh.indices.is_empty() {
+      self.chunks.remove(&pos);
+      return;
+    }
+
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for vertex in mesh.vertices.iter() {
+      for i in 0..3 {
+        min[i] = min[i].min(vertex.pos[i]);
+        max[i] = max[i].max(vertex.pos[i]);
+      }
+    }
+
+    if let Some(chunk) = self.chunks.get_mut(&pos) {
+      if chunk.vcapacity >= mesh.vertices.len() && chunk.icapacity >= mesh.indices.len() {
+        self.queue.write_buffer(&chunk.vbuf, 0, bytemuck::cast_slice(&mesh.vertices));
+        self.queue.write_buffer(&chunk.ibuf, 0, bytemuck::cast_slice(&mesh.indices));
+        chunk.isize = mesh.indices.len() as u32;
+        chunk.min = min;
+        chunk.max = max;
+        return;
+      }
+    }
+
+    let vbuf = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Chunk Vertex Buffer"),
+      contents: bytemuck::cast_slice(&mesh.vertices),
+      usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    });
+    let ibuf = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+      label: Some("Chunk Index Buffer"),
+      contents: bytemuck::cast_slice(&mesh.indices),
+      usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+    });
+
+    self.chunks.insert(pos, ChunkBuffers {
+      vbuf,
+      ibuf,
+      isize: mesh.indices.len() as u32,
+      vcapacity: mesh.vertices.len(),
+      icapacity: mesh.indices.len(),
+      min,
+      max
+    });
 
   }
-  
+
+  /**
+   * get the position of every chunk being drawn
+   */
+  pub fn chunk_positions (&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+    self.chunks.keys().copied()
+  }
+
+  /**
+   * stop drawing a chunk and free its buffers
+   */
+  pub fn remove_chunk (&mut self, pos: (i32, i32)) {
+    self.chunks.remove(&pos);
+  }
+
   /**
   * draw the scene to the screen
   */
@@ -425,7 +466,7 @@ impl<'a> Render<'a> {
         depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
           view: &self.depth_texture.view,
           depth_ops: Some(wgpu::Operations {
-            load: wgpu::LoadOp::Clear(1.0),
+            load: wgpu::LoadOp::Clear(0.0),
             store: wgpu::StoreOp::Store,
           }),
           stencil_ops: None,
@@ -437,14 +478,39 @@ impl<'a> Render<'a> {
       rpass.set_pipeline(&self.pipeline);
       rpass.set_bind_group(0, &self.ubg, &[]);
       rpass.set_bind_group(1, &self.tbg, &[]);
-      rpass.set_vertex_buffer(0, self.vbuf.slice(..));
-      rpass.set_vertex_buffer(1, self.instbuf.slice(..));
-      rpass.set_index_buffer(self.ibuf.slice(..), wgpu::IndexFormat::Uint16);
-      rpass.draw_indexed(0..self.isize, 0, 0..self.instsize);
+
+      // draw each chunk that's in view from its own buffers
+      for chunk in self.chunks.values() {
+
+        if !is_box_in_frustum(&self.frustum, chunk.min, chunk.max) {
+          continue;
+        }
+
+        rpass.set_vertex_buffer(0, chunk.vbuf.slice(..));
+        rpass.set_index_buffer(chunk.ibuf.slice(..), wgpu::IndexFormat::Uint32);
+        rpass.draw_indexed(0..chunk.isize, 0, 0..1);
+
+      }
     }
     
     self.queue.submit(Some(encoder.finish()));
     frame.present();
   }
   
-}
\ No newline at end of file
+}
+
+/**
+ * check whether any part of a box is inside the frustum, by checking that it isn't entirely
+ * behind any one of the planes
+ */
+fn is_box_in_frustum (frustum: &[[f32; 4]; 6], min: [f32; 3], max: [f32; 3]) -> bool {
+
+  frustum.iter().all(|plane| {
+    // the corner of the box furthest along the plane's normal
+    let x = if plane[0] >= 0.0 { max[0] } else { min[0] };
+    let y = if plane[1] >= 0.0 { max[1] } else { min[1] };
+    let z = if plane[2] >= 0.0 { max[2] } else { min[2] };
+    plane[0] * x + plane[1] * y + plane[2] * z + plane[3] >= 0.0
+  })
+
+}
@@ -0,0 +1,401 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/*
+ * reads block states, block models, block textures and the colormaps used to tint blocks from a
+ * minecraft resource pack. packs can be either a directory or a zip file, which includes the game's
+ * own client jar
+ */
+use image::RgbaImage;
+use serde::Deserialize;
+use serde_json::Value;
+
+// only block models and textures are loaded, but they're named relative to these directories
+const BLOCKSTATES_DIR: &str = "assets/minecraft/blockstates/";
+const MODELS_DIR: &str = "assets/minecraft/models/";
+const TEXTURES_DIR: &str = "assets/minecraft/textures/";
+const COLORMAP_DIR: &str = "assets/minecraft/textures/colormap/";
+
+/**
+ * either a single value or a list of them, where only the first is used
+ */
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+  One(T),
+  Many(Vec<T>)
+}
+
+impl<T> OneOrMany<T> {
+  fn first (&self) -> Option<&T> {
+    match self {
+      OneOrMany::One(value) => Some(value),
+      OneOrMany::Many(values) => values.first()
+    }
+  }
+}
+
+/**
+ * a model chosen for a block state, and how far it's turned around the x and y axes in degrees
+ */
+#[derive(Clone, Debug, Deserialize)]
+pub struct Variant {
+  pub model: String,
+  #[serde(default)]
+  pub x: i32,
+  #[serde(default)]
+  pub y: i32
+}
+
+/**
+ * the conditions on a block state's properties for a multipart model to be used
+ */
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Condition {
+  Or {
+    #[serde(rename = "OR")]
+    or: Vec<Condition>
+  },
+  And {
+    #[serde(rename = "AND")]
+    and: Vec<Condition>
+  },
+  Properties(HashMap<String, Value>)
+}
+
+impl Condition {
+
+  /**
+   * check whether a block state's properties meet this condition. property values can list
+   * several options separated by |
+   */
+  fn matches (&self, properties: &BTreeMap<String, String>) -> bool {
+    match self {
+      Condition::Or { or } => or.iter().any(|condition| condition.matches(properties)),
+      Condition::And { and } => and.iter().all(|condition| condition.matches(properties)),
+      Condition::Properties(expected) => expected.iter().all(|(key, value)| {
+        let value = match value {
+          Value::String(value) => value.clone(),
+          value => value.to_string()
+        };
+        let actual = properties.get(key).map(String::as_str).unwrap_or("");
+        value.split('|').any(|option| option == actual)
+      })
+    }
+  }
+
+}
+
+#[derive(Deserialize)]
+struct Part {
+  when: Option<Condition>,
+  apply: OneOrMany<Variant>
+}
+
+/**
+ * the contents of a file in the blockstates directory
+ */
+#[derive(Deserialize)]
+struct Blockstate {
+  variants: Option<HashMap<String, OneOrMany<Variant>>>,
+  multipart: Option<Vec<Part>>
+}
+
+/**
+ * a face of a model element, with the texture variable it uses. uv is the area of the texture
+ * used as [x1, y1, x2, y2] in pixels out of 16, and cullface is the direction of the neighbour
+ * that hides the face
+ */
+#[derive(Clone, Debug, Deserialize)]
+pub struct ElementFace {
+  pub texture: String,
+  pub uv: Option<[f32; 4]>,
+  pub cullface: Option<String>,
+  #[serde(default)]
+  pub rotation: i32,
+  pub tintindex: Option<i32> // faces with a tint index are tinted, like by the biome they're in
+}
+
+/**
+ * how an element is turned around a point, by up to 45 degrees along a single axis
+ */
+#[derive(Clone, Debug, Deserialize)]
+pub struct ElementRotation {
+  pub origin: [f32; 3],
+  pub axis: String,
+  pub angle: f32,
+  #[serde(default)]
+  pub rescale: bool
+}
+
+/**
+ * a box within a model, from one corner to the other in pixels out of 16
+ */
+#[derive(Clone, Debug, Deserialize)]
+pub struct Element {
+  pub from: [f32; 3],
+  pub to: [f32; 3],
+  pub rotation: Option<ElementRotation>,
+  pub faces: HashMap<String, ElementFace>
+}
+
+/**
+ * the contents of a file in the models directory
+ */
+#[derive(Deserialize)]
+struct ModelFile {
+  parent: Option<String>,
+  #[serde(default)]
+  textures: HashMap<String, String>,
+  elements: Option<Vec<Element>>
+}
+
+/**
+ * a model with its parents merged in
+ */
+pub struct Model {
+  pub elements: Vec<Element>,
+  textures: HashMap<String, String>
+}
+
+impl Model {
+
+  /**
+   * follow a texture variable like #side to the name of the texture it ends up pointing at
+   */
+  pub fn texture (&self, variable: &str) -> Option<String> {
+
+    let mut texture = variable;
+    // stop eventually, in case two variables point at each other
+    for _ in 0..16 {
+      match texture.strip_prefix('#') {
+        Some(variable) => texture = self.textures.get(variable)?,
+        None => return Some(strip_namespace(texture).to_string())
+      }
+    }
+    None
+
+  }
+
+}
+
+pub struct ResourcePack {
+  blockstates: HashMap<String, Blockstate>, // keyed by block name, like oak_log
+  models: HashMap<String, ModelFile>, // keyed by model name, like block/oak_log
+  pub textures: Vec<(String, RgbaImage)>, // named like block/oak_log
+  pub colormaps: HashMap<String, RgbaImage> // named like grass
+}
+
+impl ResourcePack {
+
+  /**
+   * read the block states, models and textures from a resource pack directory or zip
+   */
+  pub fn open (path: &Path) -> io::Result<ResourcePack> {
+
+    let files = if path.is_dir() {
+      read_dir_files(path)?
+    } else {
+      read_zip_files(path)?
+    };
+
+    let mut pack = ResourcePack {
+      blockstates: HashMap::new(),
+      models: HashMap::new(),
+      textures: vec![],
+      colormaps: HashMap::new()
+    };
+
+    for (name, bytes) in files {
+
+      if let Some(block) = name.strip_prefix(BLOCKSTATES_DIR).and_then(|name| name.strip_suffix(".json")) {
+        match serde_json::from_slice(&bytes) {
+          Ok(blockstate) => { pack.blockstates.insert(block.to_string(), blockstate); }
+          Err(err) => println!("skipping block state {}: {}", name, err)
+        }
+      } else if let Some(model) = name.strip_prefix(MODELS_DIR).and_then(|name| name.strip_suffix(".json")) {
+        match serde_json::from_slice(&bytes) {
+          Ok(file) => { pack.models.insert(model.to_string(), file); }
+          Err(err) => println!("skipping model {}: {}", name, err)
+        }
+      } else if let Some(colormap) = name.strip_prefix(COLORMAP_DIR).and_then(|name| name.strip_suffix(".png")) {
+        match image::load_from_memory(&bytes) {
+          Ok(image) => { pack.colormaps.insert(colormap.to_string(), image.to_rgba8()); }
+          Err(err) => println!("skipping colormap {}: {}", name, err)
+        }
+      } else if let Some(texture) = name.strip_prefix(TEXTURES_DIR).and_then(|name| name.strip_suffix(".png")) {
+        match image::load_from_memory(&bytes) {
+          Ok(image) => pack.textures.push((texture.to_string(), image.to_rgba8())),
+          Err(err) => println!("skipping texture {}: {}", name, err)
+        }
+      }
+
+    }
+
+    println!(
+      "loaded {} block states, {} models and {} textures from {}",
+      pack.blockstates.len(), pack.models.len(), pack.textures.len(), path.display()
+    );
+
+    Ok(pack)
+
+  }
+
+  /**
+   * find the models used to draw a block state, like minecraft:oak_log with axis=x
+   */
+  pub fn variants (&self, name: &str, properties: &BTreeMap<String, String>) -> Vec<Variant> {
+
+    let Some(blockstate) = self.blockstates.get(strip_namespace(name)) else {
+      return vec![];
+    };
+
+    // a variant key lists the properties it needs, like "axis=x" or "facing=north,half=top"
+    if let Some(variants) = &blockstate.variants {
+      let variant = variants.iter().find(|(key, _)| {
+        key.split(',').filter(|condition| !condition.is_empty()).all(|condition| {
+          let (key, value) = condition.split_once('=').unwrap_or((condition, ""));
+          properties.get(key).map(String::as_str) == Some(value)
+        })
+      });
+      return variant.and_then(|(_, variant)| variant.first()).into_iter().cloned().collect();
+    }
+
+    // multipart blocks are built from every part whose conditions are met
+    if let Some(parts) = &blockstate.multipart {
+      return parts.iter()
+        .filter(|part| part.when.as_ref().is_none_or(|when| when.matches(properties)))
+        .filter_map(|part| part.apply.first().cloned())
+        .collect();
+    }
+
+    vec![]
+
+  }
+
+  /**
+   * load a model along with everything it inherits from its parents
+   */
+  pub fn model (&self, name: &str) -> Option<Model> {
+
+    let mut model = Model {
+      elements: vec![],
+      textures: HashMap::new()
+    };
+    let mut elements = None;
+
+    // children override their parents, so anything already set is kept
+    let mut next = Some(strip_namespace(name).to_string());
+    let mut depth = 0;
+    while let Some(name) = next {
+
+      let file = self.models.get(&name)?;
+      for (variable, texture) in file.textures.iter() {
+        model.textures.entry(variable.clone()).or_insert_with(|| texture.clone());
+      }
+      if elements.is_none() {
+        elements = file.elements.clone();
+      }
+
+      next = file.parent.as_deref().map(|parent| strip_namespace(parent).to_string());
+
+      // give up on parents that loop back around
+      depth += 1;
+      if depth > 32 {
+        return None;
+      }
+
+    }
+
+    model.elements = elements.unwrap_or_default();
+    Some(model)
+
+  }
+
+}
+
+/**
+ * remove the minecraft: namespace from a resource location, if it has one
+ */
+fn strip_namespace (name: &str) -> &str {
+  name.strip_prefix("minecraft:").unwrap_or(name)
+}
+
+/**
+ * check whether a file in a pack is one that gets loaded
+ */
+fn is_pack_file (name: &str) -> bool {
+  (name.starts_with(BLOCKSTATES_DIR) && name.ends_with(".json"))
+    || (name.starts_with(&format!("{}block/", MODELS_DIR)) && name.ends_with(".json"))
+    || (name.starts_with(&format!("{}block/", TEXTURES_DIR)) && name.ends_with(".png"))
+    || name == format!("{}grass.png", COLORMAP_DIR)
+    || name == format!("{}foliage.png", COLORMAP_DIR)
+}
+
+/**
+ * read the files that get loaded from a resource pack directory
+ */
+fn read_dir_files (root: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+
+  let mut files = vec![];
+  let mut dirs = vec![root.to_path_buf()];
+
+  while let Some(dir) = dirs.pop() {
+
+    for entry in std::fs::read_dir(&dir)? {
+
+      let path = entry?.path();
+      if path.is_dir() {
+        dirs.push(path);
+        continue;
+      }
+
+      // pack paths always use forward slashes, like inside a zip
+      let Ok(relative) = path.strip_prefix(root) else {
+        continue;
+      };
+      let name = relative.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+      if is_pack_file(&name) {
+        files.push((name, std::fs::read(&path)?));
+      }
+
+    }
+
+  }
+
+  Ok(files)
+
+}
+
+/**
+ * read the files that get loaded from a resource pack zip
+ */
+fn read_zip_files (path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+
+  let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+  let mut files = vec![];
+
+  for i in 0..archive.len() {
+
+    let mut file = archive.by_index(i).map_err(io::Error::other)?;
+    let name = file.name().to_string();
+    if !file.is_file() || !is_pack_file(&name) {
+      continue;
+    }
+
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+    files.push((name, bytes));
+
+  }
+
+  Ok(files)
+
+}
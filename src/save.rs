@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/*
+ * reads java edition world saves: the level.dat with the world's name and spawn point, and the
+ * folders of region files for each dimension, which are only opened once a chunk in them is needed
+ */
+use crate::region::{Region, RegionChunk};
+use crate::world::{WorldError, WorldHeight};
+use fastnbt::Value;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+/**
+ * the dimensions of a world, each stored in its own folder of region files
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dimension {
+  Overworld,
+  Nether,
+  End
+}
+
+impl Dimension {
+
+  pub const ALL: [Dimension; 3] = [Dimension::Overworld, Dimension::Nether, Dimension::End];
+
+  /**
+   * get a dimension from its name, with or without the minecraft: namespace and the_ prefix
+   */
+  pub fn from_name (name: &str) -> Option<Dimension> {
+    let name = name.strip_prefix("minecraft:").unwrap_or(name);
+    match name.strip_prefix("the_").unwrap_or(name) {
+      "overworld" => Some(Dimension::Overworld),
+      "nether" => Some(Dimension::Nether),
+      "end" => Some(Dimension::End),
+      _ => None
+    }
+  }
+
+  /**
+   * get the namespaced id of the dimension, which its dimension type shares
+   */
+  pub fn id (&self) -> &'static str {
+    match self {
+      Dimension::Overworld => "minecraft:overworld",
+      Dimension::Nether => "minecraft:the_nether",
+      Dimension::End => "minecraft:the_end"
+    }
+  }
+
+  /**
+   * get the height of the dimension in the version of the game that saved the world, if it
+   * hasn't been changed by a datapack
+   */
+  pub fn default_height (&self, data_version: Option<i32>) -> WorldHeight {
+    match self {
+      Dimension::Overworld => WorldHeight::overworld(data_version.unwrap_or(0)),
+      Dimension::Nether | Dimension::End => WorldHeight::SHORT
+    }
+  }
+
+  /**
+   * get the folder of region files for the dimension, relative to the world save folder
+   */
+  pub fn region_dir (&self) -> &'static str {
+    match self {
+      Dimension::Overworld => "region",
+      Dimension::Nether => "DIM-1/region",
+      Dimension::End => "DIM1/region"
+    }
+  }
+
+}
+
+#[derive(Deserialize)]
+struct LevelFile {
+  #[serde(rename = "Data")]
+  data: LevelFileData
+}
+
+#[derive(Deserialize)]
+struct LevelFileData {
+  #[serde(rename = "LevelName")]
+  name: Option<String>,
+  #[serde(rename = "DataVersion")]
+  data_version: Option<i32>,
+  #[serde(rename = "Version")]
+  version: Option<LevelFileVersion>,
+  #[serde(rename = "SpawnX")]
+  spawn_x: Option<i32>,
+  #[serde(rename = "SpawnY")]
+  spawn_y: Option<i32>,
+  #[serde(rename = "SpawnZ")]
+  spawn_z: Option<i32>,
+  // newer versions keep the spawn point together in one compound instead
+  spawn: Option<LevelFileSpawn>,
+  #[serde(rename = "WorldGenSettings")]
+  world_gen_settings: Option<LevelFileWorldGen>,
+  #[serde(rename = "DataPacks")]
+  data_packs: Option<LevelFileDataPacks>
+}
+
+#[derive(Deserialize)]
+struct LevelFileVersion {
+  #[serde(rename = "Name")]
+  name: String
+}
+
+#[derive(Deserialize)]
+struct LevelFileSpawn {
+  pos: fastnbt::IntArray
+}
+
+#[derive(Deserialize)]
+struct LevelFileWorldGen {
+  #[serde(default)]
+  dimensions: HashMap<String, LevelFileDimension>
+}
+
+#[derive(Deserialize)]
+struct LevelFileDimension {
+  // the name of a dimension type, or before 1.19 sometimes the whole dimension type
+  #[serde(rename = "type")]
+  dimension_type: Option<Value>
+}
+
+#[derive(Deserialize)]
+struct LevelFileDataPacks {
+  #[serde(rename = "Enabled", default)]
+  enabled: Vec<String>
+}
+
+/**
+ * the parts of a datapack's dimension type that say how tall the dimension is
+ */
+#[derive(Deserialize)]
+struct DimensionTypeFile {
+  min_y: i32,
+  height: i32
+}
+
+/**
+ * the dimension type a dimension uses, which decides how tall it is
+ */
+#[derive(Clone, Debug)]
+pub enum DimensionType {
+  // the id of a dimension type built into the game or from a datapack
+  Named(String),
+  // a dimension type written out in the level.dat
+  Inline { min_y: i32, height: i32 }
+}
+
+/**
+ * what the level.dat says about a world
+ */
+#[derive(Clone, Debug)]
+pub struct LevelData {
+  pub name: String,
+  pub version: Option<String>, // like 1.21.4
+  pub data_version: Option<i32>,
+  pub spawn: [i32; 3],
+  pub dimension_types: HashMap<String, DimensionType>, // keyed by dimension id, like minecraft:overworld
+  pub datapacks: Vec<String> // the enabled datapacks, from lowest priority to highest, like file/tall.zip
+}
+
+impl LevelData {
+
+  /**
+   * read a level.dat, which is gzipped nbt
+   */
+  pub fn read (path: &Path) -> io::Result<LevelData> {
+
+    let mut bytes = vec![];
+    GzDecoder::new(File::open(path)?).read_to_end(&mut bytes)?;
+    let level: LevelFile = fastnbt::from_bytes(&bytes).map_err(io::Error::other)?;
+    let data = level.data;
+
+    let spawn = match data.spawn {
+      Some(spawn) if spawn.pos.len() == 3 => [spawn.pos[0], spawn.pos[1], spawn.pos[2]],
+      _ => [data.spawn_x.unwrap_or(0), data.spawn_y.unwrap_or(64), data.spawn_z.unwrap_or(0)]
+    };
+
+    let dimensions = data.world_gen_settings.map(|settings| settings.dimensions).unwrap_or_default();
+    let dimension_types = dimensions.into_iter()
+      .filter_map(|(id, dimension)| {
+        let dimension_type = match dimension.dimension_type? {
+          Value::String(name) => DimensionType::Named(name),
+          Value::Compound(fields) => match (fields.get("min_y"), fields.get("height")) {
+            (Some(Value::Int(min_y)), Some(Value::Int(height))) => DimensionType::Inline { min_y: *min_y, height: *height },
+            // dimension types from before 1.17 were always 256 blocks tall from 0
+            _ => return None
+          },
+          _ => return None
+        };
+        Some((id, dimension_type))
+      })
+      .collect();
+
+    Ok(LevelData {
+      name: data.name.unwrap_or_default(),
+      version: data.version.map(|version| version.name),
+      data_version: data.data_version,
+      spawn,
+      dimension_types,
+      datapacks: data.data_packs.map(|packs| packs.enabled).unwrap_or_default()
+    })
+
+  }
+
+}
+
+/**
+ * a world save folder
+ */
+pub struct Save {
+  pub path: PathBuf,
+  pub level: LevelData,
+  pub dimensions: Vec<Dimension> // the dimensions that have a region folder
+}
+
+impl Save {
+
+  /**
+   * open a world save folder, reading its level.dat and finding which dimensions it has
+   */
+  pub fn open (path: &Path) -> io::Result<Save> {
+
+    let level = LevelData::read(&path.join("level.dat"))?;
+    let dimensions = Dimension::ALL.into_iter()
+      .filter(|dimension| path.join(dimension.region_dir()).is_dir())
+      .collect();
+
+    Ok(Save {
+      path: path.to_path_buf(),
+      level,
+      dimensions
+    })
+
+  }
+
+  /**
+   * get how tall a dimension is, from its dimension type. types that datapacks add or change are
+   * read from the world's datapacks folder, and ones that can't be found are the game's own height
+   * for the dimension. a type that's there but isn't valid is an error
+   */
+  pub fn height (&self, dimension: Dimension) -> Result<WorldHeight, WorldError> {
+
+    let height = match self.level.dimension_types.get(dimension.id()) {
+      Some(DimensionType::Inline { min_y, height }) => Some(WorldHeight::new(*min_y, *height)),
+      Some(DimensionType::Named(name)) => self.read_dimension_type(name),
+      None => self.read_dimension_type(dimension.id())
+    };
+    height.unwrap_or_else(|| Ok(dimension.default_height(self.level.data_version)))
+
+  }
+
+  /**
+   * read the height of a dimension type from the datapack that has it with the highest priority,
+   * or None if it's not in a datapack
+   */
+  fn read_dimension_type (&self, id: &str) -> Option<Result<WorldHeight, WorldError>> {
+
+    let (namespace, name) = id.split_once(':').unwrap_or(("minecraft", id));
+    let file = format!("data/{}/dimension_type/{}.json", namespace, name);
+
+    // packs in the datapacks folder are listed as file/ followed by their file name
+    let mut packs: Vec<PathBuf> = self.level.datapacks.iter()
+      .filter_map(|pack| pack.strip_prefix("file/"))
+      .map(|pack| self.path.join("datapacks").join(pack))
+      .collect();
+    // worlds from before datapacks were listed in the level.dat use every pack in the folder
+    if self.level.datapacks.is_empty() {
+      packs = std::fs::read_dir(self.path.join("datapacks")).ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+      packs.sort();
+    }
+
+    let bytes = packs.iter().rev().find_map(|pack| read_datapack_file(pack, &file))?;
+    let dimension_type: DimensionTypeFile = match serde_json::from_slice(&bytes) {
+      Ok(dimension_type) => dimension_type,
+      Err(err) => return Some(Err(WorldError::InvalidDimensionType { id: id.to_string(), reason: err.to_string() }))
+    };
+    Some(WorldHeight::new(dimension_type.min_y, dimension_type.height))
+
+  }
+
+  /**
+   * get the region files of a dimension, which are opened as they're needed
+   */
+  pub fn regions (&self, dimension: Dimension) -> RegionStore {
+    RegionStore::new(self.path.join(dimension.region_dir()))
+  }
+
+}
+
+/**
+ * a folder of region files, named r.X.Z.mca, that are each opened the first time a chunk inside
+ * them is asked for
+ */
+pub struct RegionStore {
+  dir: PathBuf,
+  regions: HashMap<(i32, i32), Option<Region>> // None if the region doesn't exist or couldn't be read
+}
+
+impl RegionStore {
+
+  /**
+   * create a store for a folder of region files, without opening any of them yet
+   */
+  pub fn new (dir: PathBuf) -> RegionStore {
+    RegionStore {
+      dir,
+      regions: HashMap::new()
+    }
+  }
+
+  /**
+   * read the compressed data of a chunk, opening the region it's in if it hasn't been already.
+   * chunks that haven't been generated are None
+   */
+  pub fn read_chunk (&mut self, chunk_x: i32, chunk_z: i32) -> Result<Option<RegionChunk>, WorldError> {
+
+    // each region holds a 32x32 grid of chunks
+    let region_pos = (chunk_x >> 5, chunk_z >> 5);
+    let dir = &self.dir;
+    let region = self.regions.entry(region_pos).or_insert_with(|| open_region(dir, region_pos));
+
+    let Some(region) = region.as_ref() else {
+      return Ok(None);
+    };
+    region.get_chunk((chunk_x & 31) as u32, (chunk_z & 31) as u32)
+
+  }
+
+  /**
+   * close the regions that don't overlap a square of chunks, from min to max inclusive. they'll be
+   * opened again if a chunk in them is asked for
+   */
+  pub fn close_outside (&mut self, min: (i32, i32), max: (i32, i32)) {
+    self.regions.retain(|(x, z), _| {
+      x * 32 <= max.0 && x * 32 + 31 >= min.0 && z * 32 <= max.1 && z * 32 + 31 >= min.1
+    });
+  }
+
+}
+
+/**
+ * read a file from a datapack folder or zip, if it's there
+ */
+fn read_datapack_file (pack: &Path, name: &str) -> Option<Vec<u8>> {
+
+  if pack.is_dir() {
+    return std::fs::read(pack.join(name)).ok();
+  }
+
+  let mut archive = zip::ZipArchive::new(File::open(pack).ok()?).ok()?;
+  let mut file = archive.by_name(name).ok()?;
+  let mut bytes = vec![];
+  file.read_to_end(&mut bytes).ok()?;
+  Some(bytes)
+
+}
+
+/**
+ * open the region file at a region position, if there is one
+ */
+fn open_region (dir: &Path, (x, z): (i32, i32)) -> Option<Region> {
+
+  let path = dir.join(format!("r.{}.{}.mca", x, z));
+  let mut file = File::open(&path).ok()?;
+
+  match Region::from_reader(&mut file) {
+    Ok(region) => Some(region),
+    Err(err) => {
+      println!("skipping region {}: {}", path.display(), err);
+      None
+    }
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use std::io::Write;
+
+  /**
+   * a world save in a temporary folder, with nothing in it but datapacks, that's deleted once the
+   * test is done with it
+   */
+  struct TestSave {
+    save: Save
+  }
+
+  impl TestSave {
+
+    fn new (name: &str, dimension_types: Vec<(&str, DimensionType)>, datapacks: &[&str]) -> TestSave {
+
+      let path = std::env::temp_dir().join(format!("webcraft-save-{}-{}", name, std::process::id()));
+      let _ = std::fs::remove_dir_all(&path);
+      std::fs::create_dir_all(path.join("datapacks")).unwrap();
+
+      let level = LevelData {
+        name: name.to_string(),
+        version: None,
+        data_version: Some(4189),
+        spawn: [0, 64, 0],
+        dimension_types: dimension_types.into_iter().map(|(id, dimension_type)| (id.to_string(), dimension_type)).collect(),
+        datapacks: datapacks.iter().map(|pack| pack.to_string()).collect()
+      };
+      TestSave { save: Save { path, level, dimensions: vec![Dimension::Overworld] } }
+
+    }
+
+    /**
+     * add a datapack folder with a dimension type in it
+     */
+    fn add_pack (&self, pack: &str, file: &str, json: &str) {
+      let path = self.save.path.join("datapacks").join(pack).join(file);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(path, json).unwrap();
+    }
+
+    /**
+     * add a zipped datapack with a dimension type in it
+     */
+    fn add_zip_pack (&self, pack: &str, file: &str, json: &str) {
+      let mut zip = zip::ZipWriter::new(File::create(self.save.path.join("datapacks").join(pack)).unwrap());
+      zip.start_file(file, zip::write::SimpleFileOptions::default()).unwrap();
+      zip.write_all(json.as_bytes()).unwrap();
+      zip.finish().unwrap();
+    }
+
+  }
+
+  impl Drop for TestSave {
+    fn drop (&mut self) {
+      let _ = std::fs::remove_dir_all(&self.save.path);
+    }
+  }
+
+  const OVERWORLD_TYPE: &str = "data/minecraft/dimension_type/overworld.json";
+
+  /**
+   * write out a dimension type
+   */
+  fn dimension_type (min_y: i32, height: i32) -> String {
+    format!("{{ \"min_y\": {}, \"height\": {}, \"logical_height\": {} }}", min_y, height, height)
+  }
+
+  #[test]
+  fn reads_inline_and_named_dimension_types () {
+
+    let test = TestSave::new("types", vec![
+      ("minecraft:overworld", DimensionType::Inline { min_y: -128, height: 512 }),
+      ("minecraft:the_nether", DimensionType::Named(String::from("example:deep")))
+    ], &["vanilla", "file/example"]);
+    test.add_pack("example", "data/example/dimension_type/deep.json", &dimension_type(-256, 512));
+
+    assert_eq!(test.save.height(Dimension::Overworld).unwrap(), WorldHeight::new(-128, 512).unwrap());
+    assert_eq!(test.save.height(Dimension::Nether).unwrap(), WorldHeight::new(-256, 512).unwrap());
+    // the end isn't in the level.dat or any datapack, so it's the usual height
+    assert_eq!(test.save.height(Dimension::End).unwrap(), WorldHeight::SHORT);
+
+    // and an inline type the game wouldn't allow is an error
+    let test = TestSave::new("bad-inline", vec![
+      ("minecraft:overworld", DimensionType::Inline { min_y: -60, height: 384 })
+    ], &[]);
+    assert!(matches!(test.save.height(Dimension::Overworld), Err(WorldError::InvalidHeight { min_y: -60, height: 384 })));
+
+  }
+
+  #[test]
+  fn prefers_the_last_enabled_datapack () {
+
+    let test = TestSave::new("priority", vec![], &["vanilla", "file/low", "file/high.zip", "file/missing"]);
+    test.add_pack("low", OVERWORLD_TYPE, &dimension_type(-64, 512));
+    test.add_zip_pack("high.zip", OVERWORLD_TYPE, &dimension_type(-128, 512));
+    // packs that are there but not enabled are left out
+    test.add_pack("zzz", OVERWORLD_TYPE, &dimension_type(0, 128));
+
+    assert_eq!(test.save.height(Dimension::Overworld).unwrap(), WorldHeight::new(-128, 512).unwrap());
+
+  }
+
+  #[test]
+  fn uses_every_datapack_without_a_list () {
+
+    // worlds without DataPacks in their level.dat use every pack, in name order
+    let test = TestSave::new("unlisted", vec![], &[]);
+    test.add_pack("b", OVERWORLD_TYPE, &dimension_type(-128, 512));
+    test.add_pack("a", OVERWORLD_TYPE, &dimension_type(-64, 512));
+    assert_eq!(test.save.height(Dimension::Overworld).unwrap(), WorldHeight::new(-128, 512).unwrap());
+
+    // and without a datapacks folder either, the height is the default
+    std::fs::remove_dir_all(test.save.path.join("datapacks")).unwrap();
+    assert_eq!(test.save.height(Dimension::Overworld).unwrap(), WorldHeight::TALL);
+
+  }
+
+  #[test]
+  fn reports_broken_dimension_types () {
+
+    let test = TestSave::new("broken", vec![], &["file/pack"]);
+    test.add_pack("pack", OVERWORLD_TYPE, "{ \"min_y\": -64, ");
+    test.add_pack("pack", "data/minecraft/dimension_type/the_nether.json", &dimension_type(7, 256));
+
+    let err = test.save.height(Dimension::Overworld).unwrap_err();
+    assert!(matches!(&err, WorldError::InvalidDimensionType { id, .. } if id == "minecraft:overworld"), "{}", err);
+    assert!(matches!(test.save.height(Dimension::Nether), Err(WorldError::InvalidHeight { min_y: 7, height: 256 })));
+    // which leaves the end as it is
+    assert_eq!(test.save.height(Dimension::End).unwrap(), WorldHeight::SHORT);
+
+  }
+
+}
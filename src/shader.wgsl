@@ -4,43 +4,79 @@ struct Uniforms {
 };
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
 
-struct InstanceIn {
-    @location(5) position: vec3<i32>,
-    @location(6) block_type: u32
-}
+// the layout of the block atlas
+struct AtlasInfo {
+    columns: u32,
+    tile_size: u32,
+    padding: u32,
+    _padding: u32,
+};
+
+@group(1) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(1) @binding(1)
+var s_diffuse: sampler;
+@group(1) @binding(2)
+var<uniform> atlas: AtlasInfo;
 
 struct VertexIn {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>
+    @location(2) normal: vec3<f32>,
+    @location(3) tile: u32,
+    @location(4) tint: vec3<f32>,
+    @location(5) light: vec2<f32>
 }
 
 struct VertexOut {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
-    @location(1) normal: vec3<f32>
+    @location(1) normal: vec3<f32>,
+    @location(2) @interpolate(flat) tile: u32,
+    @location(3) tint: vec3<f32>,
+    @location(4) light: vec2<f32>
 }
 
 @vertex
-fn vs_main(in: VertexIn, instance: InstanceIn) -> VertexOut {
+fn vs_main(in: VertexIn) -> VertexOut {
 
     var out: VertexOut;
 
-    let x = in.position.x + f32(instance.position.x * 2);
-    let y = in.position.y + f32(instance.position.y * 2);
-    let z = in.position.z + f32(instance.position.z * 2);
-
-    out.position = uniforms.view_proj * vec4<f32>(x, y, z, 1.0);
+    out.position = uniforms.view_proj * vec4<f32>(in.position, 1.0);
     out.tex_coords = in.tex_coords;
     out.normal = in.normal;
+    out.tile = in.tile;
+    out.tint = in.tint;
+    out.light = in.light;
 
     return out;
 }
 
-@group(1) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(1) @binding(1)
-var s_diffuse: sampler;
+// how bright a light level is, from 0 to 1. each level is a little dimmer than the last, like in
+// the game
+fn level_brightness(level: f32) -> f32 {
+    let f = level / 15.0;
+    return f / (4.0 - 3.0 * f);
+}
+
+// the colour a face is lit, in linear rgb, from its block and sky light levels. this follows the
+// game's lightmap in the middle of the day, at the default brightness setting
+fn light_colour(light: vec2<f32>) -> vec3<f32> {
+
+    // light from blocks like torches is warmer than daylight
+    let block = level_brightness(light.x);
+    let sky = level_brightness(light.y);
+    let block_colour = vec3<f32>(block, block * ((block * 0.6 + 0.4) * 0.6 + 0.4), block * (block * block * 0.6 + 0.4));
+
+    // even unlit faces are faintly visible
+    var colour = mix(clamp(block_colour + vec3<f32>(sky), vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(0.75), 0.04);
+
+    // brighten the darker levels
+    let inverse = vec3<f32>(1.0) - colour;
+    colour = mix(colour, vec3<f32>(1.0) - inverse * inverse * inverse * inverse, 0.5);
+
+    return pow(colour, vec3<f32>(2.2));
+}
 
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
@@ -53,5 +89,24 @@ fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
         shading = 0.5;
     }
 
-    return shading * textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    // find the tile inside its border. merged faces span several blocks, so the texture repeats
+    // across each of them
+    let cell = atlas.tile_size + atlas.padding * 2u;
+    let origin = vec2<f32>(
+        f32((in.tile % atlas.columns) * cell + atlas.padding),
+        f32((in.tile / atlas.columns) * cell + atlas.padding)
+    );
+    let pixel = origin + fract(in.tex_coords) * f32(atlas.tile_size);
+    let uv = pixel / vec2<f32>(textureDimensions(t_diffuse));
+
+    let color = textureSample(t_diffuse, s_diffuse, uv);
+
+    // cut out the see-through parts of textures like leaves and glass
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    // grass, leaves and water are tinted by the biomes around them, and everything is lit by the
+    // light where it is
+    return vec4<f32>(shading * color.rgb * in.tint * light_colour(in.light), color.a);
 }
\ No newline at end of file
@@ -1,11 +1,13 @@
+use std::collections::HashMap;
 use std::fmt::Error;
 
 /*
-* manage textures, both images and depth buffers
+* manage textures, both images and depth buffers, and the atlas that block textures are packed into
 */
-use image::GenericImageView;
+use image::{imageops, GenericImageView, Rgba, RgbaImage};
 
 pub struct Texture {
+  #[allow(dead_code)]
   pub texture: wgpu::Texture,
   pub view: wgpu::TextureView,
   pub sampler: wgpu::Sampler,
@@ -43,7 +45,7 @@ impl Texture {
       mag_filter: wgpu::FilterMode::Linear,
       min_filter: wgpu::FilterMode::Linear,
       mipmap_filter: wgpu::FilterMode::Nearest,
-      compare: Some(wgpu::CompareFunction::LessEqual),
+      compare: Some(wgpu::CompareFunction::GreaterEqual),
       lod_min_clamp: 0.0,
       lod_max_clamp: 100.0,
       ..Default::default()
@@ -56,16 +58,6 @@ impl Texture {
     }
   }
   
-  /**
-   * load bytes into an image
-   */
-  pub fn from_bytes(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self, Error> {
-
-    let img = image::load_from_memory(bytes).unwrap();
-    Self::from_image(device, queue, &img, Some(label))
-
-  }
-  
   /**
    * create a texture
    */
@@ -123,4 +115,327 @@ impl Texture {
     Ok(Self { texture, view, sampler })
   }
   
-}
\ No newline at end of file
+}
+
+// the textures that come with the viewer, named after the blocks they're for. they're built
+// into the binary so they're found wherever it's run from
+const BUILT_IN_TEXTURES: &[(&str, &[u8])] = &[
+  ("amethyst_block", include_bytes!("textures/amethyst_block.png")),
+  ("andesite", include_bytes!("textures/andesite.png")),
+  ("bedrock", include_bytes!("textures/bedrock.png")),
+  ("birch_leaves", include_bytes!("textures/birch_leaves.png")),
+  ("birch_log", include_bytes!("textures/birch_log.png")),
+  ("birch_log_top", include_bytes!("textures/birch_log_top.png")),
+  ("budding_amethyst", include_bytes!("textures/budding_amethyst.png")),
+  ("calcite", include_bytes!("textures/calcite.png")),
+  ("chest", include_bytes!("textures/chest.png")),
+  ("clay", include_bytes!("textures/clay.png")),
+  ("coal_ore", include_bytes!("textures/coal_ore.png")),
+  ("cobblestone", include_bytes!("textures/cobblestone.png")),
+  ("copper_ore", include_bytes!("textures/copper_ore.png")),
+  ("dandelion", include_bytes!("textures/dandelion.png")),
+  ("deepslate", include_bytes!("textures/deepslate.png")),
+  ("deepslate_coal_ore", include_bytes!("textures/deepslate_coal_ore.png")),
+  ("deepslate_copper_ore", include_bytes!("textures/deepslate_copper_ore.png")),
+  ("deepslate_diamond_ore", include_bytes!("textures/deepslate_diamond_ore.png")),
+  ("deepslate_gold_ore", include_bytes!("textures/deepslate_gold_ore.png")),
+  ("deepslate_iron_ore", include_bytes!("textures/deepslate_iron_ore.png")),
+  ("deepslate_lapis_ore", include_bytes!("textures/deepslate_lapis_ore.png")),
+  ("deepslate_redstone_ore", include_bytes!("textures/deepslate_redstone_ore.png")),
+  ("deepslate_top", include_bytes!("textures/deepslate_top.png")),
+  ("diamond_ore", include_bytes!("textures/diamond_ore.png")),
+  ("diorite", include_bytes!("textures/diorite.png")),
+  ("dirt", include_bytes!("textures/dirt.png")),
+  ("gold_ore", include_bytes!("textures/gold_ore.png")),
+  ("granite", include_bytes!("textures/granite.png")),
+  ("grass_block_bottom", include_bytes!("textures/grass_block_bottom.png")),
+  ("grass_block_side", include_bytes!("textures/grass_block_side.png")),
+  ("grass_block_top", include_bytes!("textures/grass_block_top.png")),
+  ("gravel", include_bytes!("textures/gravel.png")),
+  ("iron_ore", include_bytes!("textures/iron_ore.png")),
+  ("lapis_ore", include_bytes!("textures/lapis_ore.png")),
+  ("lava", include_bytes!("textures/lava.png")),
+  ("magma_block", include_bytes!("textures/magma_block.png")),
+  ("moss_block", include_bytes!("textures/moss_block.png")),
+  ("mossy_cobblestone", include_bytes!("textures/mossy_cobblestone.png")),
+  ("oak_leaves", include_bytes!("textures/oak_leaves.png")),
+  ("oak_log", include_bytes!("textures/oak_log.png")),
+  ("oak_log_top", include_bytes!("textures/oak_log_top.png")),
+  ("poppy", include_bytes!("textures/poppy.png")),
+  ("redstone_ore", include_bytes!("textures/redstone_ore.png")),
+  ("sand", include_bytes!("textures/sand.png")),
+  ("sandstone", include_bytes!("textures/sandstone.png")),
+  ("sandstone_bottom", include_bytes!("textures/sandstone_bottom.png")),
+  ("sandstone_top", include_bytes!("textures/sandstone_top.png")),
+  ("short_grass", include_bytes!("textures/short_grass.png")),
+  ("spawner", include_bytes!("textures/spawner.png")),
+  ("stone", include_bytes!("textures/stone.png")),
+  ("sugar_cane", include_bytes!("textures/sugar_cane.png")),
+  ("tuff", include_bytes!("textures/tuff.png")),
+  ("water", include_bytes!("textures/water.png"))
+];
+
+/**
+ * a grid of block textures packed into a single image, with each tile surrounded by a border of
+ * its own edge pixels so neighbouring tiles never bleed into each other
+ */
+pub struct Atlas {
+  pub image: RgbaImage,
+  pub tile_size: u32,
+  pub columns: u32,
+  tiles: HashMap<String, u32>,
+  translucent: Vec<bool> // indexed by tile, whether any of its pixels aren't fully opaque
+}
+
+impl Atlas {
+
+  // pixels of border around each tile
+  pub const PADDING: u32 = 1;
+  // the tile used for blocks without a texture
+  pub const MISSING: u32 = 0;
+
+  /**
+   * pack named textures into an atlas. every tile is the size of the widest texture, and only the
+   * top square of taller textures (like the frames of animated ones) is used
+   */
+  pub fn new (textures: Vec<(String, RgbaImage)>) -> Atlas {
+
+    let tile_size = textures.iter().map(|(_, texture)| texture.width()).max().unwrap_or(16).max(1);
+
+    // the missing texture comes first, followed by the rest sorted so the layout is repeatable
+    let mut textures = textures;
+    textures.sort_by(|a, b| a.0.cmp(&b.0));
+    textures.insert(0, (String::from("missing"), missing_texture(tile_size)));
+
+    let count = textures.len() as u32;
+    let columns = (count as f32).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns);
+    let cell = tile_size + Atlas::PADDING * 2;
+
+    let mut atlas = Atlas {
+      image: RgbaImage::new(columns * cell, rows * cell),
+      tile_size,
+      columns,
+      tiles: HashMap::new(),
+      translucent: vec![]
+    };
+
+    for (tile, (name, texture)) in textures.into_iter().enumerate() {
+
+      // crop to a square, then scale up to the tile size
+      let side = texture.width().min(texture.height());
+      let square = imageops::crop_imm(&texture, 0, 0, side, side).to_image();
+      let square = if side == tile_size {
+        square
+      } else {
+        imageops::resize(&square, tile_size, tile_size, imageops::FilterType::Nearest)
+      };
+
+      atlas.place(tile as u32, &square);
+      atlas.tiles.entry(name).or_insert(tile as u32);
+      atlas.translucent.push(square.pixels().any(|pixel| pixel[3] < 255));
+
+    }
+
+    atlas
+
+  }
+
+  /**
+   * pack the textures that come with the viewer into an atlas
+   */
+  pub fn built_in () -> Atlas {
+
+    let mut textures = vec![];
+
+    for (name, png) in BUILT_IN_TEXTURES {
+      match image::load_from_memory_with_format(png, image::ImageFormat::Png) {
+        Ok(texture) => textures.push((name.to_string(), texture.to_rgba8())),
+        Err(err) => println!("skipping texture {}: {}", name, err)
+      }
+    }
+
+    Atlas::new(textures)
+
+  }
+
+  /**
+   * get the tile of a texture by name
+   */
+  pub fn tile (&self, name: &str) -> Option<u32> {
+    self.tiles.get(name).copied()
+  }
+
+  /**
+   * check whether a tile can be partly seen through, because some of its pixels aren't opaque
+   */
+  pub fn is_translucent (&self, tile: u32) -> bool {
+    self.translucent.get(tile as usize).copied().unwrap_or(false)
+  }
+
+  /**
+   * get the pixel position of the top left corner of a tile, inside its border
+   */
+  pub fn tile_origin (&self, tile: u32) -> (u32, u32) {
+    let cell = self.tile_size + Atlas::PADDING * 2;
+    ((tile % self.columns) * cell + Atlas::PADDING, (tile / self.columns) * cell + Atlas::PADDING)
+  }
+
+  /**
+   * copy a texture into a tile, then extend its edges out over the border
+   */
+  fn place (&mut self, tile: u32, texture: &RgbaImage) {
+
+    let (left, top) = self.tile_origin(tile);
+    imageops::replace(&mut self.image, texture, left as i64, top as i64);
+
+    let size = self.tile_size as i64;
+    let padding = Atlas::PADDING as i64;
+    for y in -padding..size + padding {
+      for x in -padding..size + padding {
+        if (0..size).contains(&x) && (0..size).contains(&y) {
+          continue;
+        }
+        let pixel = *texture.get_pixel(x.clamp(0, size - 1) as u32, y.clamp(0, size - 1) as u32);
+        self.image.put_pixel((left as i64 + x) as u32, (top as i64 + y) as u32, pixel);
+      }
+    }
+
+  }
+
+}
+
+/**
+ * create the magenta and black checkerboard used for blocks without a texture
+ */
+fn missing_texture (size: u32) -> RgbaImage {
+
+  let half = (size / 2).max(1);
+  RgbaImage::from_fn(size, size, |x, y| {
+    if (x / half + y / half).is_multiple_of(2) {
+      Rgba([248, 0, 248, 255])
+    } else {
+      Rgba([0, 0, 0, 255])
+    }
+  })
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  /**
+   * create a 2x2 texture with a different colour in each pixel, based on a shade
+   */
+  fn texture (shade: u8) -> RgbaImage {
+    RgbaImage::from_fn(2, 2, |x, y| Rgba([shade, x as u8 * 100, y as u8 * 100, 255]))
+  }
+
+  #[test]
+  fn lays_out_tiles_in_a_grid () {
+
+    let atlas = Atlas::new(vec![
+      (String::from("c"), texture(30)),
+      (String::from("a"), texture(10)),
+      (String::from("b"), texture(20))
+    ]);
+
+    // the missing texture and three others make a 2x2 grid of 2 pixel tiles, each with a border
+    assert_eq!(atlas.tile_size, 2);
+    assert_eq!(atlas.columns, 2);
+    assert_eq!(atlas.image.dimensions(), (8, 8));
+
+    // the missing texture comes first, then the rest by name
+    assert_eq!(Atlas::MISSING, 0);
+    assert_eq!(atlas.tile("missing"), Some(Atlas::MISSING));
+    assert_eq!(atlas.tile("a"), Some(1));
+    assert_eq!(atlas.tile("b"), Some(2));
+    assert_eq!(atlas.tile("c"), Some(3));
+    assert_eq!(atlas.tile("d"), None);
+
+    assert_eq!(atlas.tile_origin(0), (1, 1));
+    assert_eq!(atlas.tile_origin(1), (5, 1));
+    assert_eq!(atlas.tile_origin(2), (1, 5));
+    assert_eq!(atlas.tile_origin(3), (5, 5));
+    assert_eq!(*atlas.image.get_pixel(1, 1), Rgba([248, 0, 248, 255]));
+    assert!(!atlas.is_translucent(1));
+
+  }
+
+  #[test]
+  fn loads_built_in_textures () {
+
+    let atlas = Atlas::built_in();
+    for (name, _) in BUILT_IN_TEXTURES {
+      assert!(atlas.tile(name).is_some(), "{} didn't load", name);
+    }
+    assert_eq!(atlas.tile_size, 16);
+
+  }
+
+  #[test]
+  fn finds_translucent_tiles () {
+
+    let mut glass = texture(10);
+    glass.put_pixel(1, 0, Rgba([10, 100, 0, 0]));
+    let atlas = Atlas::new(vec![(String::from("glass"), glass), (String::from("stone"), texture(20))]);
+
+    assert!(atlas.is_translucent(atlas.tile("glass").unwrap()));
+    assert!(!atlas.is_translucent(atlas.tile("stone").unwrap()));
+    assert!(!atlas.is_translucent(Atlas::MISSING));
+
+  }
+
+  #[test]
+  fn pads_tiles_with_their_edges () {
+
+    let atlas = Atlas::new(vec![(String::from("a"), texture(10))]);
+    let (left, top) = atlas.tile_origin(atlas.tile("a").unwrap());
+    let texel = |x: u32, y: u32| *texture(10).get_pixel(x, y);
+
+    // the tile itself is copied in as it is
+    for y in 0..2 {
+      for x in 0..2 {
+        assert_eq!(*atlas.image.get_pixel(left + x, top + y), texel(x, y));
+      }
+    }
+
+    // the border repeats the nearest pixel on the edge, with the corners filled by the corners
+    let size = atlas.tile_size;
+    assert_eq!(Atlas::PADDING, 1);
+    for i in 0..size {
+      assert_eq!(*atlas.image.get_pixel(left + i, top - 1), texel(i, 0));
+      assert_eq!(*atlas.image.get_pixel(left + i, top + size), texel(i, size - 1));
+      assert_eq!(*atlas.image.get_pixel(left - 1, top + i), texel(0, i));
+      assert_eq!(*atlas.image.get_pixel(left + size, top + i), texel(size - 1, i));
+    }
+    assert_eq!(*atlas.image.get_pixel(left - 1, top - 1), texel(0, 0));
+    assert_eq!(*atlas.image.get_pixel(left + size, top + size), texel(size - 1, size - 1));
+
+  }
+
+  #[test]
+  fn fits_textures_to_the_tile_size () {
+
+    // tiles are as wide as the widest texture, taller textures are cut down to their top square,
+    // and smaller ones are scaled up
+    let tall = RgbaImage::from_fn(4, 8, |_, y| Rgba([if y < 4 { 200 } else { 50 }, 0, 0, 255]));
+    let small = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 90, 255]));
+    let atlas = Atlas::new(vec![(String::from("tall"), tall), (String::from("small"), small)]);
+    assert_eq!(atlas.tile_size, 4);
+
+    for name in ["tall", "small"] {
+      let (left, top) = atlas.tile_origin(atlas.tile(name).unwrap());
+      let expected = if name == "tall" { Rgba([200, 0, 0, 255]) } else { Rgba([0, 0, 90, 255]) };
+      for y in 0..4 {
+        for x in 0..4 {
+          assert_eq!(*atlas.image.get_pixel(left + x, top + y), expected, "{} at {}, {}", name, x, y);
+        }
+      }
+    }
+
+  }
+
+}
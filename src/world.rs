@@ -1,86 +1,608 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
+use std::io;
+use std::sync::{Arc, Mutex};
 
 /*
  * manages the world state, like loaded chunks and blocks
  */
-use crate::render::Instance;
-use mca_parser::{nbt::BlockState, Chunk as McaChunk, Region};
+use crate::biomes::{BiomeRegistry, PLAINS};
+use crate::blocks::{StateRegistry, AIR_STATE};
+use crate::chunk_format::{ChunkData, SectionBiomes, SectionLightData};
+use crate::loader::LoadJob;
+use crate::region::{Region, RegionChunk};
+use crate::save::RegionStore;
 
-const AIR: u32 = 0;
-const MOSS: u32 = 1;
+// 21w37a, the 1.18 snapshot that stretched the overworld from 0 to 256 out to -64 to 320
+const TALL_OVERWORLD_VERSION: i32 = 2834;
+
+/**
+ * something wrong with a world file or one of the chunks in it
+ */
+#[derive(Debug)]
+pub enum WorldError {
+  // the file couldn't be read
+  Io(io::Error),
+  // the region file is too short to have a header, or a chunk runs past the end of it
+  CorruptRegion(String),
+  // a chunk couldn't be decompressed, or is compressed in a way that isn't supported
+  Decompression(String),
+  // a chunk isn't laid out the way it should be
+  Nbt(fastnbt::error::Error),
+  // a section of a chunk has no block states
+  MissingBlockStates { section: i32 },
+  // a section of a chunk refers to a block past the end of its palette
+  PaletteIndexOutOfRange { section: i32, index: usize, palette_len: usize },
+  // a section of a chunk has blocks above or below the height of its dimension
+  SectionOutOfRange { section: i32, height: WorldHeight },
+  // a dimension's height isn't one the game allows
+  InvalidHeight { min_y: i32, height: i32 },
+  // a datapack's dimension type couldn't be read
+  InvalidDimensionType { id: String, reason: String },
+  // the world has more distinct block states than a section can tell apart
+  TooManyStates { section: i32 }
+}
+
+impl fmt::Display for WorldError {
+
+  fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      WorldError::Io(err) => write!(f, "couldn't read the file: {}", err),
+      WorldError::CorruptRegion(reason) => write!(f, "the region file is corrupt: {}", reason),
+      WorldError::Decompression(reason) => write!(f, "couldn't decompress the chunk: {}", reason),
+      WorldError::Nbt(err) => write!(f, "couldn't parse the chunk: {}", err),
+      WorldError::MissingBlockStates { section } => write!(f, "section {} has no block states", section),
+      WorldError::PaletteIndexOutOfRange { section, index, palette_len } => {
+        write!(f, "section {} uses palette entry {}, but its palette only has {}", section, index, palette_len)
+      }
+      WorldError::SectionOutOfRange { section, height } => {
+        write!(f, "section {} is outside the world, which goes from y {} to {}", section, height.min_y, height.max_y())
+      }
+      WorldError::InvalidHeight { min_y, height } => {
+        write!(f, "a world {} blocks tall from y {} isn't allowed", height, min_y)
+      }
+      WorldError::InvalidDimensionType { id, reason } => {
+        write!(f, "the dimension type {} is invalid: {}", id, reason)
+      }
+      WorldError::TooManyStates { section } => {
+        write!(f, "the world has more than {} block states, so section {} can't be stored", u16::MAX as u32 + 1, section)
+      }
+    }
+  }
+
+}
+
+impl std::error::Error for WorldError {}
+
+impl From<io::Error> for WorldError {
+
+  fn from (err: io::Error) -> WorldError {
+    WorldError::Io(err)
+  }
+
+}
+
+impl From<fastnbt::error::Error> for WorldError {
+
+  fn from (err: fastnbt::error::Error) -> WorldError {
+    WorldError::Nbt(err)
+  }
+
+}
+
+/**
+ * the range of y a dimension's blocks can be in, from min_y up to but not including max_y
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorldHeight {
+  pub min_y: i32,
+  pub height: i32
+}
+
+impl WorldHeight {
+
+  // every dimension before 1.18, and the nether and end since
+  pub const SHORT: WorldHeight = WorldHeight { min_y: 0, height: 256 };
+  // the overworld since 1.18
+  pub const TALL: WorldHeight = WorldHeight { min_y: -64, height: 384 };
+
+  /**
+   * create a height, like one from a datapack's dimension type. the game only allows whole
+   * sections, with every block between y -2032 and 2031
+   */
+  pub fn new (min_y: i32, height: i32) -> Result<WorldHeight, WorldError> {
+
+    let valid = min_y % 16 == 0 && height % 16 == 0 && height >= 16 && min_y >= -2032 && min_y + height <= 2032;
+    if !valid {
+      return Err(WorldError::InvalidHeight { min_y, height });
+    }
+    Ok(WorldHeight { min_y, height })
+
+  }
+
+  /**
+   * get the height of the vanilla overworld in the version of the game that saved a chunk or world
+   */
+  pub fn overworld (data_version: i32) -> WorldHeight {
+    if data_version >= TALL_OVERWORLD_VERSION {
+      WorldHeight::TALL
+    } else {
+      WorldHeight::SHORT
+    }
+  }
+
+  /**
+   * get the y just above the highest block
+   */
+  pub fn max_y (&self) -> i32 {
+    self.min_y + self.height
+  }
+
+  /**
+   * get the y of the lowest section
+   */
+  pub fn min_section (&self) -> i32 {
+    self.min_y >> 4
+  }
+
+  /**
+   * get how many sections tall the world is
+   */
+  pub fn section_count (&self) -> usize {
+    (self.height >> 4) as usize
+  }
+
+  /**
+   * check whether a section y is within the world
+   */
+  pub fn contains_section (&self, section_y: i32) -> bool {
+    section_y >= self.min_section() && section_y < self.min_section() + self.section_count() as i32
+  }
+
+}
+
+/**
+ * a 16x16x16 cube of blocks, stored densely as state ids
+ */
+pub struct ChunkSection {
+  blocks: Box<[u16; 4096]> // [y][z][x]
+}
+
+impl ChunkSection {
+
+  /**
+   * create a section full of air
+   */
+  pub fn new () -> ChunkSection {
+    ChunkSection {
+      blocks: Box::new([AIR_STATE as u16; 4096])
+    }
+  }
+
+  /**
+   * get the block state at a position within the section
+   */
+  pub fn get_block (&self, x: usize, y: usize, z: usize) -> u32 {
+    self.blocks[y << 8 | z << 4 | x] as u32
+  }
+
+  /**
+   * set the block state at a position within the section
+   */
+  #[cfg(test)]
+  pub fn set_block (&mut self, x: usize, y: usize, z: usize, state: u16) {
+    self.blocks[y << 8 | z << 4 | x] = state;
+  }
+
+}
+
+/**
+ * how brightly a block is lit, by light from blocks like torches and by light from the sky, each
+ * from 0 to 15
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Light {
+  pub block: u8,
+  pub sky: u8
+}
+
+impl Light {
+
+  // out in the open, with nothing above
+  pub const FULL_SKY: Light = Light { block: 0, sky: 15 };
+
+}
+
+/**
+ * the light levels of a 16x16x16 cube of blocks, stored as half a byte for each block [y][z][x]
+ * with the lower half first
+ */
+#[derive(Clone, Default)]
+pub struct SectionLight {
+  block: Option<Box<[u8; 2048]>>, // None if no light from blocks reaches the section
+  sky: Option<Box<[u8; 2048]>> // None if the whole section is in full sky light
+}
+
+impl SectionLight {
+
+  /**
+   * get the light at a position within the section
+   */
+  pub fn get_light (&self, x: usize, y: usize, z: usize) -> Light {
+
+    let i = y << 8 | z << 4 | x;
+    let nibble = |array: &Option<Box<[u8; 2048]>>| array.as_ref().map(|array| array[i / 2] >> ((i % 2) * 4) & 15);
+
+    Light {
+      block: nibble(&self.block).unwrap_or(0),
+      sky: nibble(&self.sky).unwrap_or(15)
+    }
+
+  }
 
-pub struct Block {
-  pub id: u32,
-  pub x: i32,
-  pub y: i32,
-  pub z: i32
 }
 
 pub struct Chunk {
-  pub blocks: Vec<Block>, // chunks are 16x384x16 [z][y][x]
+  pub sections: Vec<Option<ChunkSection>>, // a section for each 16 blocks of height from the bottom up, None if all air
+  pub biomes: Vec<[u16; 64]>, // the biome of each 4x4x4 cell [y][z][x] of each section
+  pub light: Vec<SectionLight>, // the light in each section, whether or not it has any blocks
+  pub height: WorldHeight,
   pub x: i32,
   pub z: i32
 }
 
 impl Chunk {
 
+  /**
+   * create an empty chunk as tall as its world
+   */
+  pub fn new (x: i32, z: i32, height: WorldHeight) -> Chunk {
+
+    Chunk {
+      sections: (0..height.section_count()).map(|_| None).collect(),
+      biomes: vec![[PLAINS; 64]; height.section_count()],
+      light: vec![SectionLight::default(); height.section_count()],
+      height,
+      x,
+      z
+    }
+
+  }
+
   /**
    * create a test chunk
    */
-  pub fn test () -> Chunk {
+  #[cfg(test)]
+  pub fn test (states: &mut StateRegistry) -> Chunk {
 
-    // create an empty chunk
-    let mut blocks: Vec<Block> = vec![];
+    // create an empty chunk, the same height as an overworld chunk
+    let mut chunk = Chunk::new(0, 0, WorldHeight::TALL);
+    let moss = states.register(crate::blocks::BlockState::new("moss_block", std::collections::BTreeMap::new()));
 
     // place a few moss blocks
     for x in 0..16 {
 
       for z in 0..16 {
 
-        blocks.push(Block {
-          id: MOSS,
-          x: x as i32,
-          y: 0,
-          z: z as i32
-        });
+        chunk.set_block(x, 0, z, moss).unwrap();
 
       }
 
     }
 
-    blocks.push(Block {
-      id: MOSS,
-      x: 1,
-      y: 1,
-      z: 1
-    });
+    chunk.set_block(1, 1, 1, moss).unwrap();
 
-    Chunk {
-      blocks,
-      x: 0,
-      z: 0
+    chunk
+
+  }
+
+  /**
+   * create a chunk from the sections read from a region file, registering the block states and
+   * biomes in their palettes. sections with blocks outside the height of the world are an error
+   */
+  pub fn from_data (data: ChunkData, height: WorldHeight, states: &mut StateRegistry, biomes: &mut BiomeRegistry) -> Result<Chunk, WorldError> {
+
+    let mut chunk = Chunk::new(data.x, data.z, height);
+    let mut sky_light = vec![None; height.section_count()];
+
+    // chunks are broken up into 16x16x16 sections
+    for section in data.sections {
+
+      // the game saves an extra section above and below the world to hold light, without blocks
+      let index = chunk.section_index(section.y);
+      if let (Some(index), Some(section_biomes)) = (index, section.biomes) {
+        chunk.biomes[index] = register_biomes(section_biomes, biomes);
+      }
+      if let Some(index) = index {
+        let SectionLightData { block, sky } = section.light;
+        chunk.light[index].block = block.and_then(nibble_array);
+        sky_light[index] = sky.and_then(nibble_array);
+      }
+      let blocks = match (index, section.blocks) {
+        (_, Some(blocks)) => blocks,
+        (None, None) => continue,
+        (Some(_), None) => return Err(WorldError::MissingBlockStates { section: section.y })
+      };
+      // register each palette entry once for the whole section. sections store states in 16 bits,
+      // so worlds with more than that many can't be shown
+      let palette: Vec<u16> = blocks.palette.into_iter()
+        .map(|state| u16::try_from(states.register(state)).map_err(|_| WorldError::TooManyStates { section: section.y }))
+        .collect::<Result<_, _>>()?;
+
+      // sections that are entirely air don't need any storage
+      if palette.iter().all(|state| *state as u32 == AIR_STATE) {
+        continue;
+      }
+      let index = index.ok_or(WorldError::SectionOutOfRange { section: section.y, height })?;
+
+      let mut section_blocks = ChunkSection::new();
+      match blocks.indices {
+        Some(indices) => {
+          for (i, index) in indices.into_iter().enumerate() {
+            section_blocks.blocks[i] = *palette.get(index as usize).ok_or(WorldError::PaletteIndexOutOfRange {
+              section: section.y,
+              index: index as usize,
+              palette_len: palette.len()
+            })?;
+          }
+        }
+        // a section without indices is filled entirely with the first palette entry
+        None => section_blocks.blocks.fill(palette[0])
+      }
+
+      chunk.sections[index] = Some(section_blocks);
+
+    }
+
+    for (light, sky) in chunk.light.iter_mut().zip(carry_sky_light(sky_light)) {
+      light.sky = sky;
+    }
+
+    Ok(chunk)
+
+  }
+
+  /**
+   * get the index into sections of the section at a section y, if it's in this chunk
+   */
+  fn section_index (&self, section_y: i32) -> Option<usize> {
+
+    if !self.height.contains_section(section_y) {
+      return None;
+    }
+    Some((section_y - self.height.min_section()) as usize)
+
+  }
+
+  /**
+   * get the biome at a position within the chunk, where y is the world y. positions above or below
+   * the chunk use the biome of the highest or lowest section
+   */
+  pub fn get_biome (&self, x: usize, y: i32, z: usize) -> u16 {
+
+    let index = ((y >> 4) - self.height.min_section()).clamp(0, self.biomes.len() as i32 - 1);
+    let Some(section) = self.biomes.get(index as usize) else {
+      return PLAINS;
+    };
+    section[((y & 0xf) as usize >> 2) << 4 | (z >> 2) << 2 | x >> 2]
+
+  }
+
+  /**
+   * get the light at a position within the chunk, where y is the world y. positions above or below
+   * the chunk use the light of the top or bottom layer of blocks
+   */
+  pub fn get_light (&self, x: usize, y: i32, z: usize) -> Light {
+
+    let y = y.clamp(self.height.min_y, self.height.max_y() - 1);
+    let Some(light) = self.section_index(y >> 4).map(|index| &self.light[index]) else {
+      return Light::FULL_SKY;
+    };
+    light.get_light(x, (y & 0xf) as usize, z)
+
+  }
+
+  /**
+   * get the corners of the box around the sections of the chunk that aren't empty, in world block
+   * positions, or None if the whole chunk is air
+   */
+  pub fn bounds (&self) -> Option<([f32; 3], [f32; 3])> {
+
+    let lowest = self.sections.iter().position(Option::is_some)?;
+    let highest = self.sections.iter().rposition(Option::is_some)?;
+    let min_y = self.height.min_y + lowest as i32 * 16;
+    let max_y = self.height.min_y + (highest as i32 + 1) * 16;
+
+    Some((
+      [(self.x * 16) as f32, min_y as f32, (self.z * 16) as f32],
+      [(self.x * 16 + 16) as f32, max_y as f32, (self.z * 16 + 16) as f32]
+    ))
+
+  }
+
+  /**
+   * get the block state at a position within the chunk, where y is the world y
+   */
+  pub fn get_block (&self, x: usize, y: i32, z: usize) -> u32 {
+
+    let section = self.section_index(y >> 4).and_then(|index| self.sections[index].as_ref());
+    match section {
+      Some(section) => section.get_block(x, (y & 0xf) as usize, z),
+      None => AIR_STATE
     }
 
   }
 
   /**
-   * create an instance vector for rending from each block in this chunk
+   * set the block state at a position within the chunk, where y is the world y. blocks outside
+   * the height of the chunk are ignored, and states too big for a section to store are an error
    */
-  pub fn gen_instances (&self) -> Vec<Instance> {
+  #[cfg(test)]
+  pub fn set_block (&mut self, x: usize, y: i32, z: usize, state: u32) -> Result<(), WorldError> {
 
-    self.blocks.iter().map(|block| {
-      Instance {
-        pos: [self.x * 16 + block.x, block.y, self.z * 16 + block.z],
-        block: block.id
+    let state = u16::try_from(state).map_err(|_| WorldError::TooManyStates { section: y >> 4 })?;
+    let Some(index) = self.section_index(y >> 4) else {
+      return Ok(());
+    };
+
+    // only allocate a section once something other than air is placed in it
+    let section = &mut self.sections[index];
+    if section.is_none() && state as u32 == AIR_STATE {
+      return Ok(());
+    }
+    section.get_or_insert_with(ChunkSection::new).set_block(x, (y & 0xf) as usize, z, state);
+    Ok(())
+
+  }
+
+}
+
+/**
+ * register the biomes in a section's palette, and get the biome of each of its cells
+ */
+fn register_biomes (section: SectionBiomes, biomes: &mut BiomeRegistry) -> [u16; 64] {
+
+  let palette: Vec<u16> = section.palette.iter().map(|name| biomes.register(name)).collect();
+  let Some(first) = palette.first() else {
+    return [PLAINS; 64];
+  };
+
+  match section.indices {
+    // indices past the end of the palette are left as the first biome
+    Some(indices) => std::array::from_fn(|i| indices.get(i).and_then(|index| palette.get(*index as usize)).copied().unwrap_or(*first)),
+    None => [*first; 64]
+  }
+
+}
+
+/**
+ * turn the light saved in a section into half a byte for each block. arrays that are the wrong
+ * length are thrown away, the same as if the section had none saved
+ */
+fn nibble_array (light: Vec<u8>) -> Option<Box<[u8; 2048]>> {
+  light.into_boxed_slice().try_into().ok()
+}
+
+/**
+ * fill in the sky light of the sections of a chunk, from the bottom up, that don't have any saved.
+ * the game leaves it out where it's the same all the way down as the bottom of the section above,
+ * so the bottom layer of the nearest section above with sky light is carried down. sections with
+ * none above them are in full sky light
+ */
+fn carry_sky_light (saved: Vec<Option<Box<[u8; 2048]>>>) -> Vec<Option<Box<[u8; 2048]>>> {
+
+  let mut bottom: Option<[u8; 128]> = None;
+  let mut sections: Vec<Option<Box<[u8; 2048]>>> = saved.into_iter().rev().map(|sky| {
+    let sky = match sky {
+      Some(sky) => {
+        bottom = Some(std::array::from_fn(|i| sky[i]));
+        Some(sky)
       }
-    }).collect()
+      None => bottom.map(|layer| Box::new(std::array::from_fn(|i| layer[i % 128])))
+    };
+    // sections entirely in full sky light don't need any storage
+    sky.filter(|sky| sky.iter().any(|byte| *byte != 0xff))
+  }).collect();
+
+  sections.reverse();
+  sections
+
+}
+
+// the chunks around a chunk: east, west, south and north, then the four corners
+const NEIGHBOURS: [(i32, i32); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+/**
+ * a chunk along with the loaded chunks around it, which is everything needed to mesh it away from
+ * the rest of the world. the chunks on its corners are needed too, since biome colours are blended
+ * across them
+ */
+#[derive(Clone)]
+pub struct ChunkArea {
+  pub chunk: Arc<Chunk>,
+  neighbours: [Option<Arc<Chunk>>; 8] // in the order of NEIGHBOURS
+}
+
+impl ChunkArea {
+
+  /**
+   * get the chunk a position relative to the middle chunk is in, if it's loaded
+   */
+  fn chunk_at (&self, pos: [i32; 3]) -> Option<&Arc<Chunk>> {
+
+    let offset = (pos[0].div_euclid(16), pos[2].div_euclid(16));
+    if offset == (0, 0) {
+      return Some(&self.chunk);
+    }
+    let index = NEIGHBOURS.iter().position(|neighbour| *neighbour == offset)?;
+    self.neighbours[index].as_ref()
+
+  }
+
+  /**
+   * get the block state at a position relative to the middle chunk, which can be up to a chunk
+   * outside of it on each side. blocks in chunks that aren't loaded are air
+   */
+  pub fn get_block (&self, pos: [i32; 3]) -> u32 {
+
+    match self.chunk_at(pos) {
+      Some(chunk) => chunk.get_block(pos[0].rem_euclid(16) as usize, pos[1], pos[2].rem_euclid(16) as usize),
+      None => AIR_STATE
+    }
+
+  }
+
+  /**
+   * get the light at a position relative to the middle chunk, which can be up to a chunk outside of
+   * it on each side. positions in chunks that aren't loaded are in full sky light
+   */
+  pub fn get_light (&self, pos: [i32; 3]) -> Light {
+
+    match self.chunk_at(pos) {
+      Some(chunk) => chunk.get_light(pos[0].rem_euclid(16) as usize, pos[1], pos[2].rem_euclid(16) as usize),
+      None => Light::FULL_SKY
+    }
 
   }
 
+  /**
+   * get the biome at a position relative to the middle chunk, which can be up to a chunk outside
+   * of it on each side. positions in chunks that aren't loaded use the nearest biome in the middle
+   * chunk instead
+   */
+  pub fn get_biome (&self, pos: [i32; 3]) -> u16 {
+
+    match self.chunk_at(pos) {
+      Some(chunk) => chunk.get_biome(pos[0].rem_euclid(16) as usize, pos[1], pos[2].rem_euclid(16) as usize),
+      None => self.chunk.get_biome(pos[0].clamp(0, 15) as usize, pos[1], pos[2].clamp(0, 15) as usize)
+    }
+
+  }
+
+}
+
+/**
+ * what a call to World::stream changed: the chunks it unloaded, and the jobs to start for loading
+ * chunks in the background
+ */
+#[derive(Default)]
+pub struct StreamUpdate {
+  pub unloaded: Vec<(i32, i32)>,
+  pub jobs: Vec<LoadJob>
 }
 
 pub struct World {
-  pub loaded_chunks: Vec<Chunk>
+  pub loaded_chunks: HashMap<(i32, i32), Arc<Chunk>>, // keyed by chunk x and z
+  pub states: StateRegistry,
+  pub biomes: BiomeRegistry,
+  regions: Option<Arc<Mutex<RegionStore>>>, // where to load more chunks from, for worlds opened from a save
+  height: Option<WorldHeight>, // None for a single region file, where each chunk's height comes from its DataVersion
+  unparsed: Vec<RegionChunk>, // chunks read from a region file that haven't been handed out to parse yet
+  parsing: usize, // how many of those are being parsed
+  loading: HashSet<(i32, i32)>, // chunks being loaded from the region files in the background
+  missing: HashSet<(i32, i32)>, // chunks that were looked for but haven't been generated
+  kept: Option<((i32, i32), i32)> // the chunk that chunks are kept loaded around, and how far
 }
 
 impl World {
@@ -88,126 +610,436 @@ impl World {
   /**
    * create a test world
    */
+  #[cfg(test)]
   pub fn test () -> World {
 
     // create a test chunk
-    let chunk = Chunk::test();
+    let mut states = StateRegistry::new();
+    let chunk = Chunk::test(&mut states);
     // and add it to the world
-    let loaded_chunks = vec![chunk];
+    let loaded_chunks = HashMap::from([((chunk.x, chunk.z), Arc::new(chunk))]);
 
-    return World {
-      loaded_chunks
+    World {
+      loaded_chunks,
+      states,
+      biomes: BiomeRegistry::new(),
+      regions: None,
+      height: Some(WorldHeight::TALL),
+      unparsed: vec![],
+      parsing: 0,
+      loading: HashSet::new(),
+      missing: HashSet::new(),
+      kept: None
     }
-    
+
   }
 
   /**
-   * parse a world from a file
+   * read a world from a single region file. the chunks are decompressed and parsed in the
+   * background, once World::stream hands them out. chunks that can't be found in the region are
+   * skipped, and the region only fails to load if it can't be read at all
    */
-  pub fn parse_world (file: &mut File) -> World {
-
-    // create a list of chunks
-    let mut chunks: Vec<Chunk> = vec![];
+  pub fn parse_world (file: &mut File) -> Result<World, WorldError> {
 
     // load the region
-    let region = Region::from_reader(file).unwrap();
-
-    // for now, just load the first chunk
-    let chunk = region.get_chunk(0, 0).unwrap();
-
-    if let Some(chunk) = chunk {
-      // parse the raw chunk data into structured NBT format
-      let parsed = chunk.parse().unwrap();
-      // create a list of blocks for this chunk
-      let mut blocks: Vec<Block> = vec![];
-      
-      // chunks are broken up into 16x16 sections
-      println!("section count: {}", parsed.sections.len());
-      parsed.sections.iter().for_each(|section| {
-        let y_offset = section.y as i32 * 16;
-
-        let block_states = section.block_states.as_ref().unwrap();
-        let palette = block_states.palette.clone();
-        let data = block_states.data.clone();
-        if data.is_some() {
-
-          // there are multiple blocks in this section
-          let section_blocks = data.unwrap();
-
-          println!("there are {} blocks in this section", section_blocks.len());
-
-          section_blocks.iter().enumerate().for_each(|(i, block)| {
-            let x = i as i32 & 0xf;
-            let z = (i as i32 >> 4) & 0xf;
-            let y = y_offset + (i >> 8) as i32;
-            
-            // only keep going if block is actually in palette
-            if (*block as usize) != 0 {
-
-              println!("new block at {} {} {}", x, y, z);
-              // add it to the chunk
-              blocks.push(Block {
-                id: MOSS,
-                x,
-                y,
-                z
-              });
-  
-            }
-  
-          });
-
-        } else {
-          // all blocks in this section are the same
-          let block = palette.get(0).unwrap();
-
-          if (block.name.key != "air") {
-            // add it to the chunk
-            for i in 0..4096 {
-              let x = i as i32 & 0xf;
-              let z = (i as i32 >> 4) & 0xf;
-              let y = y_offset + (i >> 8) as i32;
-              blocks.push(Block {
-                id: MOSS,
-                x,
-                y,
-                z
-              });
-            }
+    let region = Region::from_reader(file)?;
+    let mut unparsed = vec![];
+
+    // a region holds a 32x32 grid of chunks
+    for region_x in 0..32 {
+
+      for region_z in 0..32 {
+
+        // chunks that have not been generated are left empty in the region
+        match region.get_chunk(region_x, region_z) {
+          Ok(Some(chunk)) => unparsed.push(chunk),
+          Ok(None) => {}
+          Err(err) => println!("skipping chunk {}, {} of the region: {}", region_x, region_z, err)
+        }
+
+      }
+
+    }
+
+    println!("found {} chunks in the region", unparsed.len());
+
+    Ok(World {
+      loaded_chunks: HashMap::new(),
+      states: StateRegistry::new(),
+      biomes: BiomeRegistry::new(),
+      regions: None,
+      height: None,
+      unparsed,
+      parsing: 0,
+      loading: HashSet::new(),
+      missing: HashSet::new(),
+      kept: None
+    })
+
+  }
+
+  /**
+   * create a world from the region files of a save, without loading any chunks yet. height is how
+   * tall the dimension the regions are from is
+   */
+  pub fn from_regions (regions: RegionStore, height: WorldHeight) -> World {
+
+    World {
+      loaded_chunks: HashMap::new(),
+      states: StateRegistry::new(),
+      biomes: BiomeRegistry::new(),
+      regions: Some(Arc::new(Mutex::new(regions))),
+      height: Some(height),
+      unparsed: vec![],
+      parsing: 0,
+      loading: HashSet::new(),
+      missing: HashSet::new(),
+      kept: None
+    }
+
+  }
+
+  /**
+   * start loading the chunks within a square radius of a chunk, closest first and with at most
+   * max_loading of them loading at once, and unload any that have gone more than a chunk past the
+   * radius. worlds read from a single region file hand out all their chunks the first time, and
+   * have nowhere to reload them from, so they're never unloaded
+   */
+  pub fn stream (&mut self, chunk_x: i32, chunk_z: i32, radius: i32, max_loading: usize) -> StreamUpdate {
+
+    let mut update = StreamUpdate::default();
+    update.jobs.extend(self.unparsed.drain(..).map(|data| LoadJob::Parse { data }));
+    self.parsing += update.jobs.len();
+
+    let Some(regions) = self.regions.clone() else {
+      return update;
+    };
+
+    // the extra chunk of leeway stops chunks on the edge loading and unloading over and over
+    self.kept = Some(((chunk_x, chunk_z), radius + 1));
+    let in_range = |(x, z): (i32, i32), radius: i32| (x - chunk_x).abs() <= radius && (z - chunk_z).abs() <= radius;
+
+    update.unloaded = self.loaded_chunks.keys().copied().filter(|pos| !in_range(*pos, radius + 1)).collect();
+    for pos in update.unloaded.iter() {
+      self.loaded_chunks.remove(pos);
+    }
+    self.missing.retain(|pos| in_range(*pos, radius + 1));
+    // if a worker is busy reading a region, the far ones can be closed another time
+    if let Ok(mut regions) = regions.try_lock() {
+      let reach = radius + 1;
+      regions.close_outside((chunk_x - reach, chunk_z - reach), (chunk_x + reach, chunk_z + reach));
+    }
+
+    let mut wanted: Vec<(i32, i32)> = (chunk_x - radius..=chunk_x + radius)
+      .flat_map(|x| (chunk_z - radius..=chunk_z + radius).map(move |z| (x, z)))
+      .filter(|pos| !self.loaded_chunks.contains_key(pos) && !self.missing.contains(pos) && !self.loading.contains(pos))
+      .collect();
+    wanted.sort_by_key(|(x, z)| (x - chunk_x).pow(2) + (z - chunk_z).pow(2));
+
+    // chunks that went out of range while loading still count until they arrive, so there's never
+    // more than max_loading being read
+    for pos in wanted.into_iter().take(max_loading.saturating_sub(self.loading.len())) {
+      self.loading.insert(pos);
+      update.jobs.push(LoadJob::Read { pos, regions: regions.clone() });
+    }
+
+    update
+
+  }
+
+  /**
+   * add a chunk that's finished loading in the background, returning where it was added. pos is
+   * where it was asked to be loaded from, or None if it was read from a single region file. chunks
+   * that went out of range while loading are thrown away, and ones that turned out not to exist,
+   * or couldn't be loaded, are reported and remembered so the region isn't searched for them again
+   */
+  pub fn add_chunk (&mut self, pos: Option<(i32, i32)>, data: Result<Option<ChunkData>, WorldError>) -> Option<(i32, i32)> {
+
+    let in_range = |(x, z): (i32, i32)| self.kept.is_none_or(|((kept_x, kept_z), radius)| {
+      (x - kept_x).abs() <= radius && (z - kept_z).abs() <= radius
+    });
+    match pos {
+      Some(pos) if !self.loading.remove(&pos) || !in_range(pos) => return None,
+      Some(_) => {}
+      None => self.parsing = self.parsing.saturating_sub(1)
+    }
+
+    let chunk = data.and_then(|data| data.map(|data| {
+      // without a dimension to go by, chunks are taken to be from the overworld of their version
+      let height = self.height.unwrap_or_else(|| WorldHeight::overworld(data.data_version));
+      Chunk::from_data(data, height, &mut self.states, &mut self.biomes)
+    }).transpose());
+    let chunk = match chunk {
+      Ok(Some(chunk)) => chunk,
+      result => {
+        if let Err(err) = result {
+          match pos {
+            Some((x, z)) => println!("skipping chunk {}, {}: {}", x, z, err),
+            None => println!("skipping a chunk in the region: {}", err)
           }
         }
+        self.missing.extend(pos);
+        return None;
+      }
+    };
 
-      });
+    let pos = pos.unwrap_or((chunk.x, chunk.z));
+    self.loaded_chunks.insert(pos, Arc::new(chunk));
+    Some(pos)
 
-      println!("chunk at {} {}", parsed.x_pos, parsed.z_pos);
+  }
 
-      // define the chunk and add it to the world
-      chunks.push(Chunk {
-        blocks,
-        x: parsed.x_pos,
-        z: parsed.z_pos
-      });
+  /**
+   * get the range of y the world's blocks can be in, from the bottom of the lowest chunk to the
+   * top of the highest. None if it's from a single region file and nothing has loaded yet
+   */
+  pub fn y_range (&self) -> Option<(i32, i32)> {
 
-    } else {
-        // If the chunk is None, it has not been generated
-        println!("Chunk has not been generated.");
+    if let Some(height) = self.height {
+      return Some((height.min_y, height.max_y()));
     }
+    let min_y = self.loaded_chunks.values().map(|chunk| chunk.height.min_y).min()?;
+    let max_y = self.loaded_chunks.values().map(|chunk| chunk.height.max_y()).max()?;
+    Some((min_y, max_y))
 
-    println!("loaded {} blocks", chunks.get(0).unwrap().blocks.len());
+  }
 
-    World {
-      loaded_chunks: chunks
+  /**
+   * check whether any chunks are still being loaded in the background
+   */
+  pub fn is_loading (&self) -> bool {
+    !self.loading.is_empty() || self.parsing > 0 || !self.unparsed.is_empty()
+  }
+
+  /**
+   * get the chunks whose meshes are out of date after some chunks were loaded or unloaded. that's
+   * the new chunks, along with the loaded chunks around them since the faces along their shared
+   * edges may have been covered up or uncovered, and biome colours blend across them
+   */
+  pub fn changed_chunks (&self, changed: &[(i32, i32)]) -> HashSet<(i32, i32)> {
+
+    changed.iter()
+      .flat_map(|(x, z)| std::iter::once((*x, *z)).chain(NEIGHBOURS.map(|(dx, dz)| (x + dx, z + dz))))
+      .filter(|pos| self.loaded_chunks.contains_key(pos))
+      .collect()
+
+  }
+
+  /**
+   * get a loaded chunk along with the loaded chunks around it, for meshing it
+   */
+  pub fn chunk_area (&self, pos: (i32, i32)) -> Option<ChunkArea> {
+
+    let chunk = self.loaded_chunks.get(&pos)?.clone();
+    let neighbours = NEIGHBOURS.map(|(x, z)| self.loaded_chunks.get(&(pos.0 + x, pos.1 + z)).cloned());
+
+    Some(ChunkArea {
+      chunk,
+      neighbours
+    })
+
+  }
+
+  /**
+   * find the loaded chunk closest to a block position, ignoring height
+   */
+  pub fn nearest_chunk (&self, x: f32, z: f32) -> Option<&Chunk> {
+
+    let distance = |chunk: &Chunk| {
+      let dx = (chunk.x * 16 + 8) as f32 - x;
+      let dz = (chunk.z * 16 + 8) as f32 - z;
+      dx * dx + dz * dz
+    };
+    self.loaded_chunks.values().map(Arc::as_ref).min_by(|a, b| distance(a).total_cmp(&distance(b)))
+
+  }
+
+  /**
+   * find the chunk in the middle of the loaded chunks
+   */
+  pub fn centre_chunk (&self) -> Option<&Chunk> {
+
+    let count = self.loaded_chunks.len().max(1) as f32;
+    let x = self.loaded_chunks.values().map(|chunk| (chunk.x * 16 + 8) as f32).sum::<f32>() / count;
+    let z = self.loaded_chunks.values().map(|chunk| (chunk.z * 16 + 8) as f32).sum::<f32>() / count;
+    self.nearest_chunk(x, z)
+
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::blocks::BlockState;
+  use crate::chunk_format::{SectionBlocks, SectionData};
+  use std::collections::BTreeMap;
+
+  /**
+   * create a section filled with a single block, with no light saved
+   */
+  fn section (y: i32, name: &str) -> SectionData {
+    SectionData {
+      y,
+      blocks: Some(SectionBlocks { palette: vec![BlockState::new(name, BTreeMap::new())], indices: None }),
+      biomes: None,
+      light: SectionLightData { block: None, sky: None }
     }
+  }
 
+  /**
+   * create a chunk from some sections, in a world of a height
+   */
+  fn chunk_from (sections: Vec<SectionData>, height: WorldHeight) -> Result<Chunk, WorldError> {
+    let data = ChunkData { x: 0, z: 0, data_version: 4189, sections };
+    Chunk::from_data(data, height, &mut StateRegistry::new(), &mut BiomeRegistry::new())
   }
 
   /**
-   * generate instances for each block in the world
+   * create a section filled with a single block, with light saved as it is in the game
    */
-  pub fn gen_instances (&self) -> Vec<Instance> {
+  fn lit_section (y: i32, name: &str, block: Option<Vec<u8>>, sky: Option<Vec<u8>>) -> SectionData {
+    SectionData { light: SectionLightData { block, sky }, ..section(y, name) }
+  }
+
+  #[test]
+  fn reads_the_lower_half_of_each_byte_first () {
+
+    // each byte holds two blocks along x, the first in its lower half
+    let mut block = vec![0; 2048];
+    let mut sky = vec![0; 2048];
+    block[0] = 0x21;
+    sky[0] = 0x43;
+    block[8] = 0x05; // x 0, z 1
+    sky[128] = 0xe0; // x 1, y 1
+    let chunk = chunk_from(vec![lit_section(0, "air", Some(block), Some(sky))], WorldHeight::SHORT).unwrap();
+
+    assert_eq!(chunk.get_light(0, 0, 0), Light { block: 1, sky: 3 });
+    assert_eq!(chunk.get_light(1, 0, 0), Light { block: 2, sky: 4 });
+    assert_eq!(chunk.get_light(0, 0, 1), Light { block: 5, sky: 0 });
+    assert_eq!(chunk.get_light(0, 1, 0), Light { block: 0, sky: 0 });
+    assert_eq!(chunk.get_light(1, 1, 0), Light { block: 0, sky: 14 });
+
+  }
+
+  #[test]
+  fn carries_sky_light_down_from_above () {
+
+    // only section 1 has sky light saved, with its bottom layer lit from 0 along x, and the rest
+    // fully lit
+    let mut sky = vec![0xff; 2048];
+    for (i, byte) in sky[..128].iter_mut().enumerate() {
+      let x = (i * 2 % 16) as u8;
+      *byte = x | (x + 1) << 4;
+    }
+    let chunk = chunk_from(vec![
+      lit_section(0, "stone", None, None),
+      lit_section(1, "air", None, Some(sky)),
+      lit_section(2, "air", None, None)
+    ], WorldHeight::SHORT).unwrap();
+
+    // the sections below take the bottom layer of the one above all the way down
+    for y in [0, 7, 15, 16] {
+      for x in 0..16 {
+        assert_eq!(chunk.get_light(x, y, 9).sky, x as u8, "x {} y {}", x, y);
+      }
+    }
+    assert_eq!(chunk.get_light(3, 17, 9).sky, 15);
+
+    // while the ones above it, and anything over the top of the world, are in full sky
+    for y in [32, 47, 200, 255, 300] {
+      assert_eq!(chunk.get_light(3, y, 9), Light::FULL_SKY, "y {}", y);
+    }
 
-    self.loaded_chunks.iter().flat_map(|chunk| chunk.gen_instances()).collect()
+    // as is a chunk with no sky light saved at all
+    let chunk = chunk_from(vec![lit_section(0, "stone", None, None)], WorldHeight::SHORT).unwrap();
+    assert_eq!(chunk.get_light(3, 0, 9), Light::FULL_SKY);
 
   }
 
-}
\ No newline at end of file
+  #[test]
+  fn checks_world_heights () {
+
+    assert_eq!(WorldHeight::new(-64, 384).unwrap(), WorldHeight::TALL);
+    assert_eq!(WorldHeight::new(0, 256).unwrap(), WorldHeight::SHORT);
+    assert_eq!(WorldHeight::new(-2032, 4064).unwrap().max_y(), 2032);
+    assert_eq!(WorldHeight::new(2016, 16).unwrap().section_count(), 1);
+
+    for (min_y, height) in [
+      // not whole sections
+      (0, 250), (-60, 384), (8, 256),
+      // no blocks at all
+      (0, 0), (0, -16),
+      // past the lowest or highest block the game allows
+      (-2048, 256), (-2032, 4080), (1920, 128), (2032, 16)
+    ] {
+      assert!(
+        matches!(WorldHeight::new(min_y, height), Err(WorldError::InvalidHeight { .. })),
+        "{} from {} was allowed", height, min_y
+      );
+    }
+
+  }
+
+  #[test]
+  fn rejects_sections_outside_the_world () {
+
+    // sections just inside the top and bottom are fine, as are the empty ones the game saves
+    // around the world for light
+    let chunk = chunk_from(vec![section(-5, "air"), section(-4, "stone"), section(19, "stone"), section(20, "air")], WorldHeight::TALL).unwrap();
+    assert_eq!(chunk.bounds().map(|(min, max)| (min[1], max[1])), Some((-64.0, 320.0)));
+
+    for y in [-5, 20] {
+      let err = chunk_from(vec![section(y, "stone")], WorldHeight::TALL).err();
+      assert!(matches!(err, Some(WorldError::SectionOutOfRange { section, height: WorldHeight::TALL }) if section == y));
+    }
+    // like a tall chunk in a world that's meant to be short
+    assert!(chunk_from(vec![section(-1, "stone")], WorldHeight::SHORT).is_err());
+
+  }
+
+  #[test]
+  fn rejects_states_too_big_for_a_section () {
+
+    let mut chunk = Chunk::new(0, 0, WorldHeight::TALL);
+    chunk.set_block(3, 10, 4, u16::MAX as u32).unwrap();
+    assert_eq!(chunk.get_block(3, 10, 4), u16::MAX as u32);
+
+    // rather than wrapping around to air
+    assert!(matches!(chunk.set_block(3, 10, 4, u16::MAX as u32 + 1), Err(WorldError::TooManyStates { section: 0 })));
+    assert_eq!(chunk.get_block(3, 10, 4), u16::MAX as u32);
+
+  }
+
+  #[test]
+  fn counts_chunks_loading_until_they_arrive () {
+
+    let mut world = World::from_regions(RegionStore::new(std::path::PathBuf::from("nowhere")), WorldHeight::TALL);
+    let read = |update: &StreamUpdate| -> Vec<(i32, i32)> {
+      update.jobs.iter().filter_map(|job| match job {
+        LoadJob::Read { pos, .. } => Some(*pos),
+        LoadJob::Parse { .. } => None
+      }).collect()
+    };
+
+    // the nearest chunks start loading first, up to the limit
+    let first = read(&world.stream(0, 0, 2, 4));
+    assert_eq!(first.len(), 4);
+    assert!(first.iter().all(|(x, z)| x.abs() + z.abs() <= 1));
+
+    // moving away doesn't start any more while those are still being read
+    assert!(read(&world.stream(100, 0, 2, 4)).is_empty());
+    assert!(world.is_loading());
+
+    // and once they arrive they're thrown away, rather than remembered as missing
+    for pos in first.iter() {
+      assert_eq!(world.add_chunk(Some(*pos), Ok(None)), None);
+    }
+    assert!(!world.is_loading());
+    assert!(world.missing.is_empty());
+    assert_eq!(read(&world.stream(100, 0, 2, 4)).len(), 4);
+
+  }
+
+}